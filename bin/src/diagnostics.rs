@@ -0,0 +1,160 @@
+use std::{
+    io::{self, IsTerminal},
+    ops::Range,
+};
+
+use ariadne::{sources, Color, Config, Label, Report, ReportKind};
+use interpreter::{
+    check::{Diagnostic, Severity},
+    error::RuntimeError,
+};
+use parser::{error::LangError, parser::Span};
+
+type SourceSpan = (String, Range<usize>);
+
+/// Spans are byte offsets, but ariadne counts in characters
+fn char_range(source: &str, span: Span) -> Range<usize> {
+    let to_char = |byte: usize| source[..byte.min(source.len())].chars().count();
+    to_char(span.start)..to_char(span.end)
+}
+
+fn report(kind: ReportKind, name: &str, offset: usize) -> ariadne::ReportBuilder<SourceSpan> {
+    Report::build(kind, name.to_string(), offset)
+        .with_config(Config::default().with_color(io::stderr().is_terminal()))
+}
+
+fn label<M: ToString>(
+    name: &str,
+    range: Range<usize>,
+    message: M,
+    color: Color,
+) -> Label<SourceSpan> {
+    let label = Label::new((name.to_string(), range)).with_message(message);
+    // labels are colored regardless of the report config
+    if io::stderr().is_terminal() {
+        label.with_color(color)
+    } else {
+        label
+    }
+}
+
+fn eprint(report: Report<SourceSpan>, name: &str, source: &str) {
+    // if the report can't be rendered there is nowhere left to report that to
+    report
+        .eprint(sources(vec![(name.to_string(), source)]))
+        .ok();
+}
+
+pub fn report_syntax_error(error: &LangError, name: &str, source: &str) {
+    match error {
+        LangError::SyntaxError { span, message, .. } => {
+            let range = char_range(source, *span);
+            let report = report(ReportKind::Error, name, range.start)
+                .with_message("Syntax error")
+                .with_label(label(name, range, message, Color::Red))
+                .finish();
+            eprint(report, name, source);
+        }
+    }
+}
+
+pub fn report_diagnostic(diagnostic: &Diagnostic, name: &str, source: &str) {
+    let (kind, title, color) = match diagnostic.severity {
+        Severity::Error => (ReportKind::Error, "Invalid transformation", Color::Red),
+        Severity::Warning => (
+            ReportKind::Warning,
+            "Suspicious transformation",
+            Color::Yellow,
+        ),
+    };
+    let range = char_range(source, diagnostic.span);
+    let mut report = report(kind, name, range.start)
+        .with_message(title)
+        .with_label(label(name, range, &diagnostic.message, color));
+    if let Some(note) = &diagnostic.note {
+        report = report.with_note(note);
+    }
+    eprint(report.finish(), name, source);
+}
+
+pub fn report_runtime_error(error: &RuntimeError, name: &str, source: &str) {
+    let (title, message, note) = match error.root() {
+        RuntimeError::PatternMismatch(message, _) => (
+            "Pattern mismatch",
+            message.clone(),
+            Some("if other values are expected here, handle them with a conditional transformation `? ... : ...`"),
+        ),
+        RuntimeError::ValueError(message, _) => ("Runtime error", message.clone(), None),
+        RuntimeError::TypeMismatch(expected, found, _) => (
+            "Type mismatch",
+            format!("expected a value of type {}, found {}", expected, found),
+            Some("the first type of a cast `::#from~>#to` must be the type of the value"),
+        ),
+        RuntimeError::NoBranchMatched(branches, _) => (
+            "No branch matched",
+            format!(
+                "none of the {} branches of this conditional transformation matched",
+                branches.len()
+            ),
+            None,
+        ),
+        RuntimeError::Context { .. } | RuntimeError::Trace { .. } | RuntimeError::Fatal(_) => {
+            unreachable!()
+        }
+    };
+    // a mismatch is only fatal if it happened while constructing
+    let note = if error.root().is_mismatch() && !error.is_mismatch() {
+        Some("this happened while constructing a value, so conditional transformations did not try their other branches")
+    } else {
+        note
+    };
+
+    let primary = error.span().map(|span| char_range(source, span));
+    let mut report = report(
+        ReportKind::Error,
+        name,
+        primary.as_ref().map_or(0, |r| r.start),
+    )
+    .with_message(title);
+
+    match &primary {
+        Some(range) => {
+            report =
+                report.with_label(label(name, range.clone(), &message, Color::Red).with_order(0))
+        }
+        // nothing to point at, so the message goes in a note instead
+        None => report = report.with_note(&message),
+    }
+
+    // recursive calls label the same call site over and over, only the innermost one is kept
+    let mut seen = primary.iter().cloned().collect::<Vec<_>>();
+    for (span, message) in error.labels() {
+        let range = char_range(source, span);
+        if seen.contains(&range) {
+            continue;
+        }
+        seen.push(range.clone());
+        report = report
+            .with_label(label(name, range, message, Color::Yellow).with_order(seen.len() as i32));
+    }
+
+    if let Some(note) = note {
+        report = report.with_note(note);
+    }
+
+    eprint(report.finish(), name, source);
+
+    let backtrace = error.backtrace();
+    if !backtrace.is_empty() {
+        eprintln!("Backtrace (most recent call first):");
+        for (i, frame) in backtrace.iter().enumerate() {
+            match frame.call {
+                Some(span) => {
+                    let (line, column) = span.position(source);
+                    eprintln!("  {}: {}, called at {}:{}:{}", i, frame, name, line, column)
+                }
+                None => eprintln!("  {}: {}", i, frame),
+            }
+        }
+    }
+}
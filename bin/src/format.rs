@@ -0,0 +1,161 @@
+use interpreter::{interpret::evaluate, number::Number, traits::Value};
+use parser::parser::{is_identifier, quote, Lexer};
+
+use crate::diagnostics::{report_runtime_error, report_syntax_error};
+
+const INPUT_NAME: &str = "<input>";
+
+pub const INPUT_FORMATS: &[&str] = &["string", "literal", "json"];
+pub const OUTPUT_FORMATS: &[&str] = &["display", "raw", "json", "literal"];
+
+/// Turns the text given as input into the value passed to the program, reporting why if it can't
+pub fn parse_input(format: &str, input: &str) -> Option<Value> {
+    match format {
+        "string" => Some(Value::String(input.to_string())),
+        "literal" => {
+            let expr = match Lexer::new(input, None).parse_expression() {
+                Ok(e) => e,
+                Err(e) => {
+                    report_syntax_error(&e, INPUT_NAME, input);
+                    return None;
+                }
+            };
+            match evaluate(&expr) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    report_runtime_error(&e, INPUT_NAME, input);
+                    None
+                }
+            }
+        }
+        "json" => match serde_json::from_str(input) {
+            Ok(json) => match from_json(json) {
+                Ok(v) => Some(v),
+                Err(message) => {
+                    eprintln!("Invalid input: {}", message);
+                    None
+                }
+            },
+            Err(e) => {
+                eprintln!("Invalid JSON input: {}", e);
+                None
+            }
+        },
+        _ => unreachable!("clap only accepts the formats in INPUT_FORMATS"),
+    }
+}
+
+fn from_json(json: serde_json::Value) -> Result<Value, String> {
+    use serde_json::Value as Json;
+    Ok(match json {
+        // JSON numbers are kept as written, so big integers stay exact
+        Json::Number(n) => Value::Number(
+            n.to_string()
+                .parse()
+                .map_err(|_| format!("{} does not fit in a number", n))?,
+        ),
+        Json::String(s) => Value::String(s),
+        Json::Bool(b) => Value::Bool(b),
+        Json::Array(a) => Value::Array(
+            a.into_iter()
+                .map(from_json)
+                .collect::<Result<Vec<_>, _>>()?,
+        ),
+        Json::Object(o) => Value::Map(
+            o.into_iter()
+                .map(|(k, v)| Ok((k, from_json(v)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+        Json::Null => return Err("null has no equivalent value".to_string()),
+    })
+}
+
+/// Renders the value the program returned
+pub fn format_output(format: &str, value: &Value) -> String {
+    match format {
+        "display" => value.to_string(),
+        "raw" => match value {
+            Value::String(s) => s.clone(),
+            v => v.to_string(),
+        },
+        "json" => to_json(value).to_string(),
+        "literal" => {
+            let mut out = String::new();
+            write_literal(value, &mut out);
+            out
+        }
+        _ => unreachable!("clap only accepts the formats in OUTPUT_FORMATS"),
+    }
+}
+
+/// Tuples become arrays, maps become objects, fractions are rounded to floats, and numbers JSON
+/// can't hold (NaN and infinities) become `null`
+fn to_json(value: &Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+    match value {
+        Value::Number(Number::Int(i)) => Json::Number(i.to_string().parse().unwrap()),
+        Value::Number(n @ (Number::Ratio(_) | Number::Float(_))) => {
+            serde_json::Number::from_f64(n.to_f64()).map_or(Json::Null, Json::Number)
+        }
+        Value::String(s) => Json::String(s.clone()),
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Array(a) | Value::Tuple(a) => Json::Array(a.iter().map(to_json).collect()),
+        Value::Map(m) => Json::Object(m.iter().map(|(k, v)| (k.clone(), to_json(v))).collect()),
+    }
+}
+
+/// Writes `value` as an expression that evaluates back to it
+fn write_literal(value: &Value, out: &mut String) {
+    let write_list = |values: &[Value], out: &mut String| {
+        for (i, v) in values.iter().enumerate() {
+            if i != 0 {
+                out.push_str(", ");
+            }
+            write_literal(v, out);
+        }
+    };
+    match value {
+        Value::Number(Number::Int(i)) => out.push_str(&i.to_string()),
+        // fractions only come up in exact mode, where this reads back as the same fraction
+        Value::Number(Number::Ratio(r)) => out.push_str(&format!("{} / {}", r.numer(), r.denom())),
+        // there are no literals for these, but dividing by zero gives them
+        Value::Number(Number::Float(f)) if f.is_nan() => out.push_str("0.0 / 0.0"),
+        Value::Number(Number::Float(f)) if f.is_infinite() => {
+            out.push_str(if *f > 0.0 { "1.0 / 0.0" } else { "-1.0 / 0.0" })
+        }
+        // a whole float keeps its `.0` so it reads back as a float rather than an integer
+        Value::Number(Number::Float(f)) if f.fract() == 0.0 => out.push_str(&format!("{}.0", f)),
+        Value::Number(Number::Float(f)) => out.push_str(&f.to_string()),
+        Value::String(s) => out.push_str(&quote(s)),
+        Value::Bool(b) => out.push_str(&b.to_string()),
+        Value::Array(a) => {
+            out.push('[');
+            write_list(a, out);
+            out.push(']');
+        }
+        Value::Tuple(t) => {
+            out.push('(');
+            write_list(t, out);
+            if t.len() == 1 {
+                out.push(',');
+            }
+            out.push(')');
+        }
+        Value::Map(m) => {
+            out.push('{');
+            for (i, (k, v)) in m.iter().enumerate() {
+                if i != 0 {
+                    out.push_str(", ");
+                }
+                if is_identifier(k) {
+                    out.push_str(k);
+                } else {
+                    out.push_str(&quote(k));
+                }
+                out.push_str(": ");
+                write_literal(v, out);
+            }
+            out.push('}');
+        }
+    }
+}
@@ -1,8 +1,24 @@
-use std::{fs::File, io::Read, path::PathBuf, str::FromStr};
+mod diagnostics;
+mod format;
+mod repl;
 
-use ariadne::Cache;
-use clap::{App, Arg, SubCommand};
-use parser::parser::Lexer;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, IsTerminal, Read},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use diagnostics::{report_diagnostic, report_runtime_error, report_syntax_error};
+use interpreter::{
+    check::{check, Severity},
+    interpret::{call, call_reverse},
+    invertibility::{analyze, Invertibility},
+    number,
+    traits::Value,
+};
+use parser::{ast::TopLevel, internment::LocalIntern, parser::Lexer};
 
 fn main() {
     let matches = App::new("DESTRUCTION")
@@ -27,7 +43,8 @@ fn main() {
                         .long("input")
                         .takes_value(true)
                         .required(false),
-                ),
+                )
+                .args(&run_args()),
         )
         .subcommand(
             SubCommand::with_name("eval")
@@ -48,53 +65,310 @@ fn main() {
                         .short("i")
                         .long("input")
                         .takes_value(true)
-                        .required(true),
+                        .required(false),
+                )
+                .args(&run_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Check a source file and tell which of its functions can run in reverse")
+                .arg(
+                    Arg::with_name("path")
+                        .help("Path to the source file to check")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("reversible")
+                        .help("Fail unless these functions are fully reversible")
+                        .long("reversible")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("repl")
+                .about("Transform a value interactively, defining functions as you go")
+                .arg(
+                    Arg::with_name("path")
+                        .help("Source file to load functions from")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help("String to use as the starting value")
+                        .short("i")
+                        .long("input")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(input_format_arg())
+                .arg(exact_arg()),
+        )
         .get_matches();
 
-    use ariadne::FileCache;
-
     if let Some(m) = matches.subcommand_matches("build") {
         let path = m.value_of("path").unwrap();
         let mut file = File::open(path).unwrap();
         let mut contents = String::new();
         file.read_to_string(&mut contents).unwrap();
 
-        let mut cache = FileCache::default();
-        match cache.fetch(&PathBuf::from(path)) {
-            Ok(_) => (),
-            Err(_) => unreachable!(),
+        if !run(m, &contents, path, PathBuf::from_str(path).ok()) {
+            std::process::exit(1);
+        }
+    } else if let Some(m) = matches.subcommand_matches("eval") {
+        if !run(m, m.value_of("code").unwrap(), "<eval>", None) {
+            std::process::exit(1);
+        }
+    } else if let Some(m) = matches.subcommand_matches("check") {
+        let path = m.value_of("path").unwrap();
+        let mut contents = String::new();
+        File::open(path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+
+        if !check_reversibility(m, &contents, path, PathBuf::from_str(path).ok()) {
+            std::process::exit(1);
         }
+    } else if let Some(m) = matches.subcommand_matches("repl") {
+        let preload = m.value_of("path").map(|path| {
+            let mut contents = String::new();
+            File::open(path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            (path, contents)
+        });
+        number::set_exact(m.is_present("exact"));
+        let input = match format::parse_input(
+            m.value_of("input-format").unwrap(),
+            m.value_of("input").unwrap_or(""),
+        ) {
+            Some(v) => v,
+            None => std::process::exit(1),
+        };
+        repl::run(preload, input);
+    }
+}
 
-        let mut lexer = Lexer::new(&contents, PathBuf::from_str(path).ok());
-        let parsed = match lexer.parse() {
-            Ok(p) => p,
+/// Options shared by the subcommands that run a program
+fn run_args<'a, 'b>() -> [Arg<'a, 'b>; 9] {
+    [
+        Arg::with_name("input-file")
+            .help("Read the input from a file instead")
+            .long("input-file")
+            .takes_value(true)
+            .conflicts_with_all(&["input", "stdin"]),
+        Arg::with_name("stdin")
+            .help("Read the input from stdin instead, which is the default when stdin is piped")
+            .long("stdin")
+            .conflicts_with("input"),
+        Arg::with_name("lines")
+            .help("Run the program on every line of the input, printing a result per line")
+            .short("l")
+            .long("lines"),
+        Arg::with_name("skip-mismatch")
+            .help("With --lines, silently drop lines that don't match the program's patterns")
+            .long("skip-mismatch")
+            .requires("lines"),
+        input_format_arg(),
+        exact_arg(),
+        Arg::with_name("output-format")
+            .help("How to print the result: as usual, with strings unquoted, as JSON, or as a DESTRUCTION literal")
+            .long("output-format")
+            .takes_value(true)
+            .possible_values(format::OUTPUT_FORMATS)
+            .default_value("display"),
+        Arg::with_name("reverse")
+            .help("Treat the input as the output of the entry function and print the input it came from")
+            .short("r")
+            .long("reverse"),
+        Arg::with_name("entry")
+            .help("Function to run instead of `main`")
+            .short("e")
+            .long("entry")
+            .takes_value(true)
+            .default_value("main"),
+    ]
+}
+
+fn input_format_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("input-format")
+        .help("How to read the input: as a plain string, a DESTRUCTION literal like `[1, (\"a\", true)]`, or JSON")
+        .long("input-format")
+        .takes_value(true)
+        .possible_values(format::INPUT_FORMATS)
+        .default_value("string")
+}
+
+fn exact_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("exact")
+        .help("Keep arithmetic exact by dividing integers into fractions rather than floats, so it can always be reversed")
+        .long("exact")
+}
+
+/// Parses and runs `source` as described by the options of a `build` or `eval` command, returns
+/// whether it ran without errors
+fn run(m: &ArgMatches, source: &str, name: &str, file: Option<PathBuf>) -> bool {
+    let parsed = match parse(source, name, file) {
+        Some(p) => p,
+        None => return false,
+    };
+
+    number::set_exact(m.is_present("exact"));
+    let entry = LocalIntern::new(m.value_of("entry").unwrap().to_string());
+    let input_format = m.value_of("input-format").unwrap();
+    let output_format = m.value_of("output-format").unwrap();
+    let run_on = |input: Value| {
+        if m.is_present("reverse") {
+            call_reverse(&parsed, entry, input)
+        } else {
+            call(&parsed, entry, input)
+        }
+    };
+
+    let mut input = match input_source(m) {
+        Ok(i) => i,
+        Err(message) => {
+            eprintln!("{}", message);
+            return false;
+        }
+    };
+
+    if !m.is_present("lines") {
+        let mut text = String::new();
+        if let Err(e) = input.read_to_string(&mut text) {
+            eprintln!("Could not read the input: {}", e);
+            return false;
+        }
+        // files and piped text almost always end in a newline that isn't part of the input
+        if !m.is_present("input") {
+            let end = text
+                .strip_suffix("\r\n")
+                .or_else(|| text.strip_suffix('\n'))
+                .map_or(text.len(), str::len);
+            text.truncate(end);
+        }
+        let input = match format::parse_input(input_format, &text) {
+            Some(v) => v,
+            None => return false,
+        };
+        return match run_on(input) {
+            Ok(v) => {
+                println!("{}", format::format_output(output_format, &v));
+                true
+            }
             Err(e) => {
-                eprintln!("{}", e);
-                return;
+                report_runtime_error(&e, name, source);
+                false
             }
         };
+    }
 
-        let evaled = match interpreter::interpret::interpret(
-            parsed,
-            interpreter::traits::Value::String(m.value_of("input").unwrap_or("").to_string()),
-        ) {
-            Ok(v) => v,
+    // a failing line doesn't stop the others, but still makes the whole run fail
+    let mut ok = true;
+    for (i, line) in input.lines().enumerate() {
+        let line = match line {
+            Ok(l) => l,
             Err(e) => {
-                eprintln!("{}", e);
-                return;
+                eprintln!("Could not read the input: {}", e);
+                return false;
             }
         };
-        println!("{}", evaled);
-    } else if let Some(m) = matches.subcommand_matches("eval") {
-        let parsed = m.value_of("code").unwrap().parse().unwrap();
+        let input = match format::parse_input(input_format, &line) {
+            Some(v) => v,
+            None => {
+                eprintln!("in input line {}", i + 1);
+                ok = false;
+                continue;
+            }
+        };
+        match run_on(input) {
+            Ok(v) => println!("{}", format::format_output(output_format, &v)),
+            Err(e) if e.is_mismatch() && m.is_present("skip-mismatch") => (),
+            Err(e) => {
+                report_runtime_error(&e, name, source);
+                eprintln!("in input line {}", i + 1);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
 
-        let evaled = interpreter::interpret::interpret(
-            parsed,
-            interpreter::traits::Value::String(m.value_of("input").unwrap().to_string()),
-        )
-        .unwrap();
-        println!("{}", evaled);
+/// Prints how far each function of `source` can run in reverse and why, returns whether the
+/// source is valid and every function passed to `--reversible` is fully reversible
+fn check_reversibility(m: &ArgMatches, source: &str, name: &str, file: Option<PathBuf>) -> bool {
+    let parsed = match parse(source, name, file) {
+        Some(p) => p,
+        None => return false,
+    };
+
+    let analyses = analyze(&parsed);
+    for analysis in &analyses {
+        println!("{}: {}", analysis.name, analysis.invertibility);
+        for reason in &analysis.reasons {
+            let (line, column) = reason.span.position(source);
+            println!(
+                "  {}:{}:{}: {} ({})",
+                name, line, column, reason.message, reason.invertibility
+            );
+        }
+    }
+
+    let mut ok = true;
+    for function in m.values_of("reversible").into_iter().flatten() {
+        match analyses.iter().find(|a| a.name.as_str() == function) {
+            Some(a) if a.invertibility == Invertibility::Reversible => (),
+            Some(a) => {
+                eprintln!("`{}` is {}, not reversible", function, a.invertibility);
+                ok = false;
+            }
+            None => {
+                eprintln!("Missing `{}` function", function);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Where the input text comes from: `--input`, `--input-file` or stdin. Unless it is read line by
+/// line, one trailing newline of a file or stdin is dropped by `run`.
+fn input_source(m: &ArgMatches) -> Result<Box<dyn BufRead>, String> {
+    if let Some(input) = m.value_of("input") {
+        Ok(Box::new(io::Cursor::new(input.to_string())))
+    } else if let Some(path) = m.value_of("input-file") {
+        match File::open(path) {
+            Ok(f) => Ok(Box::new(BufReader::new(f))),
+            Err(e) => Err(format!("Could not read input file `{}`: {}", path, e)),
+        }
+    } else if m.is_present("stdin") || !io::stdin().is_terminal() {
+        Ok(Box::new(io::stdin().lock()))
+    } else {
+        Ok(Box::new(io::empty()))
+    }
+}
+
+/// Parses and checks `source`, reporting every syntax error and problem found in it. Warnings
+/// don't stop it from running.
+fn parse(source: &str, name: &str, file: Option<PathBuf>) -> Option<TopLevel> {
+    let (parsed, errors) = Lexer::new(source, file).parse_recovering();
+    for e in &errors {
+        report_syntax_error(e, name, source);
+    }
+    if !errors.is_empty() {
+        return None;
+    }
+    let diagnostics = check(&parsed);
+    for d in &diagnostics {
+        report_diagnostic(d, name, source);
+    }
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        None
+    } else {
+        Some(parsed)
     }
 }
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use interpreter::{
+    check::{check, check_transformation, Diagnostic, Severity},
+    interpret::{apply, apply_reverse, call_reverse},
+    traits::Value,
+};
+use parser::{
+    ast::{TopLevel, Transformation},
+    internment::LocalIntern,
+    parser::{Lexer, Sp, Tokens},
+};
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::diagnostics::{report_diagnostic, report_runtime_error, report_syntax_error};
+
+const NAME: &str = "<repl>";
+
+const HELP: &str = "\
+name := transformations;      define a function, it can span several lines until the `;`
+a -> b | c -> d               transform the current value
+:value, :v                    print the current value
+:undo, :u                     undo the last line of transformations
+:reverse, :r <function>       run a function in reverse on the current value
+:reverse, :r <transformations>
+                              run transformations in reverse on the current value
+:functions, :f                list the defined functions
+:help, :h                     print this message
+:quit, :q                     exit";
+
+struct Repl {
+    top_level: TopLevel,
+    /// Everything entered so far, so errors in functions defined on earlier lines can still point
+    /// at their source
+    source: String,
+    value: Value,
+    /// The value before each line that changed it, for `:undo`
+    history: Vec<Value>,
+}
+
+/// Whether `text` starts with `name :=`
+fn is_definition(text: &str) -> bool {
+    matches!(
+        Lexer::new(text, None)
+            .peek_many(2)
+            .iter()
+            .map(|t| &t.data)
+            .collect::<Vec<_>>()[..],
+        [Tokens::Ident(_), Tokens::Define]
+    )
+}
+
+impl Repl {
+    /// Adds `text` to the source and returns the offset it starts at
+    fn push_source(&mut self, text: &str) -> usize {
+        if !self.source.is_empty() {
+            self.source.push('\n');
+        }
+        let offset = self.source.len();
+        self.source.push_str(text);
+        offset
+    }
+
+    fn define(&mut self, offset: usize) {
+        let (parsed, errors) = Lexer::new_at(&self.source, offset, None).parse_recovering();
+        for e in &errors {
+            report_syntax_error(e, NAME, &self.source);
+        }
+        if !errors.is_empty() || !self.report(check(&parsed)) {
+            return;
+        }
+        for name in parsed.functions.keys() {
+            println!("defined `{}`", name);
+        }
+        self.top_level.functions.extend(parsed.functions);
+    }
+
+    /// Reports problems found by checking some code, returns whether it can still be run
+    fn report(&self, diagnostics: Vec<Diagnostic>) -> bool {
+        for d in &diagnostics {
+            report_diagnostic(d, NAME, &self.source);
+        }
+        diagnostics.iter().all(|d| d.severity != Severity::Error)
+    }
+
+    /// Runs transformations starting at `offset` of the source on the current value
+    fn transform(&mut self, offset: usize, reverse: bool) {
+        let pipeline = match Lexer::new_at(&self.source, offset, None).parse_pipeline() {
+            Ok(p) => p,
+            Err(e) => {
+                report_syntax_error(&e, NAME, &self.source);
+                return;
+            }
+        };
+        let diagnostics = pipeline.iter().flat_map(check_transformation).collect();
+        if !self.report(diagnostics) {
+            return;
+        }
+
+        // the whole line runs on a copy, so a failing step leaves the value as it was
+        let mut value = self.value.clone();
+        let mut run = |trans: &Sp<Transformation>| -> bool {
+            let result = if reverse {
+                apply_reverse(&self.top_level, trans, &value)
+            } else {
+                apply(&self.top_level, trans, &value)
+            };
+            match result {
+                Ok(v) => {
+                    value = v;
+                    true
+                }
+                Err(e) => {
+                    report_runtime_error(&e, NAME, &self.source);
+                    false
+                }
+            }
+        };
+        // later transformations are undone first when running in reverse
+        let ok = if reverse {
+            pipeline.iter().rev().all(&mut run)
+        } else {
+            pipeline.iter().all(&mut run)
+        };
+        if ok {
+            self.history.push(std::mem::replace(&mut self.value, value));
+            println!("{}", self.value);
+        }
+    }
+
+    fn reverse_function(&mut self, name: LocalIntern<String>) {
+        match call_reverse(&self.top_level, name, self.value.clone()) {
+            Ok(v) => {
+                self.history.push(std::mem::replace(&mut self.value, v));
+                println!("{}", self.value);
+            }
+            Err(e) => report_runtime_error(&e, NAME, &self.source),
+        }
+    }
+
+    /// Handles a line starting with `:`, returns `false` to exit
+    fn command(&mut self, line: &str) -> bool {
+        let command = line[1..].split_whitespace().next().unwrap_or("");
+        let rest = line[1 + command.len()..].trim_start();
+
+        match command {
+            "help" | "h" => println!("{}", HELP),
+            "quit" | "q" => return false,
+            "value" | "v" => println!("{}", self.value),
+            "undo" | "u" => match self.history.pop() {
+                Some(v) => {
+                    self.value = v;
+                    println!("{}", self.value);
+                }
+                None => eprintln!("Nothing to undo"),
+            },
+            "functions" | "f" => {
+                let mut names = self
+                    .top_level
+                    .functions
+                    .keys()
+                    .map(|n| n.to_string())
+                    .collect::<Vec<_>>();
+                names.sort();
+                println!("{}", names.join(", "));
+            }
+            "reverse" | "r" => {
+                let offset = self.push_source(line) + (line.len() - rest.len());
+                match Lexer::new(rest, None).peek_many(2)[..] {
+                    [Sp {
+                        data: Tokens::Ident(name),
+                        ..
+                    }] => self.reverse_function(name),
+                    _ => self.transform(offset, true),
+                }
+            }
+            _ => eprintln!("Unknown command `:{}`, see `:help`", command),
+        }
+        true
+    }
+}
+
+pub fn run(preload: Option<(&str, String)>, input: Value) {
+    let mut repl = Repl {
+        top_level: TopLevel {
+            functions: HashMap::new(),
+        },
+        source: String::new(),
+        value: input,
+        history: Vec::new(),
+    };
+
+    if let Some((path, contents)) = preload {
+        println!("loading {}", path);
+        repl.push_source(&contents);
+        repl.define(0);
+    }
+
+    let mut editor = match DefaultEditor::new() {
+        Ok(e) => e,
+        Err(e) => {
+            eprintln!("Could not start the REPL: {}", e);
+            return;
+        }
+    };
+    println!("{}", repl.value);
+
+    loop {
+        let mut line = match editor.readline("> ") {
+            Ok(l) => l,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        };
+
+        // a definition goes on until its `;`
+        if is_definition(&line) {
+            while !line.trim_end().ends_with(';') {
+                match editor.readline("... ") {
+                    Ok(l) => {
+                        line.push('\n');
+                        line.push_str(&l);
+                    }
+                    Err(ReadlineError::Interrupted) => {
+                        line.clear();
+                        break;
+                    }
+                    Err(_) => return,
+                }
+            }
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        // history is only for convenience, failing to add to it is not worth reporting
+        editor.add_history_entry(line.as_str()).ok();
+
+        let trimmed = line.trim();
+        if trimmed.starts_with(':') {
+            if !repl.command(trimmed) {
+                break;
+            }
+        } else if is_definition(trimmed) {
+            let offset = repl.push_source(trimmed);
+            repl.define(offset);
+        } else {
+            let offset = repl.push_source(trimmed);
+            repl.transform(offset, false);
+        }
+    }
+}
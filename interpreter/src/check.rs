@@ -0,0 +1,318 @@
+use parser::{
+    ast::{Expr, Expression, FormatPart, Operator, TopLevel, Transformation},
+    internment::LocalIntern,
+    parser::{Sp, Span},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The transformation fails whenever it runs, so the program is not run at all
+    Error,
+    /// The transformation may work, but likely not the way it was meant to
+    Warning,
+}
+
+/// A problem with a program found before running it
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+    pub note: Option<String>,
+}
+
+impl Diagnostic {
+    fn new(severity: Severity, message: String, span: Span) -> Self {
+        Self {
+            severity,
+            message,
+            span,
+            note: None,
+        }
+    }
+
+    fn with_note(mut self, note: &str) -> Self {
+        self.note = Some(note.to_string());
+        self
+    }
+}
+
+/// Checks every transformation of every function, in the order they appear in the source
+pub fn check(top_level: &TopLevel) -> Vec<Diagnostic> {
+    let mut functions = top_level.functions.values().collect::<Vec<_>>();
+    functions.sort_by_key(|f| f.span.start);
+    let mut diagnostics = Vec::new();
+    for function in functions {
+        for trans in &function.data {
+            check_transformation_into(trans, &mut diagnostics);
+        }
+    }
+    diagnostics
+}
+
+/// Checks that the identifiers of each `destruct -> construct` in `trans` line up:
+///
+/// - an identifier used in the construct pattern must be bound by the destruct pattern, or there
+///   is nothing to construct it from
+/// - a polyvalue has to be in both patterns, as every value the destruct pattern gives it has to
+///   be used up by the construct pattern
+/// - an identifier bound but never constructed is lost, so the transformation can't be reversed
+///   (identifiers starting with `_` are meant to be ignored, like `_`)
+///
+/// How many values a polyvalue gets depends on the repetitions around it, like `[*a] * n`, so
+/// the number of times it appears in each pattern only has to match if neither repeats it.
+pub fn check_transformation(trans: &Sp<Transformation>) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    check_transformation_into(trans, &mut diagnostics);
+    diagnostics
+}
+
+fn check_transformation_into(trans: &Sp<Transformation>, diagnostics: &mut Vec<Diagnostic>) {
+    match &trans.data {
+        Transformation::Forced {
+            destruct,
+            construct,
+        }
+        | Transformation::All {
+            destruct,
+            construct,
+        } => check_patterns(destruct, construct, diagnostics),
+        Transformation::Compound(transforms) => {
+            for t in transforms {
+                check_transformation_into(t, diagnostics);
+            }
+        }
+        Transformation::Try { first, otherwise } => {
+            check_transformation_into(first, diagnostics);
+            check_transformation_into(otherwise, diagnostics);
+        }
+    }
+}
+
+/// An identifier in a pattern
+struct Use {
+    name: LocalIntern<String>,
+    poly: bool,
+    /// Whether it is inside a `*`, which can match it any number of times
+    repeated: bool,
+    span: Span,
+}
+
+fn check_patterns(destruct: &Expression, construct: &Expression, out: &mut Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let (mut bound, mut used) = (Vec::new(), Vec::new());
+    uses(destruct, false, &mut bound);
+    uses(construct, false, &mut used);
+    let count = |uses: &[Use], u: &Use| {
+        uses.iter()
+            .filter(|v| v.name == u.name && v.poly == u.poly)
+            .count()
+    };
+    // only the first use of each identifier is reported
+    let first = |uses: &[Use], i: usize| {
+        !uses[..i]
+            .iter()
+            .any(|v| v.name == uses[i].name && v.poly == uses[i].poly)
+    };
+
+    for (i, u) in used.iter().enumerate() {
+        if !first(&used, i) || count(&bound, u) > 0 {
+            continue;
+        }
+        diagnostics.push(if u.poly {
+            Diagnostic::new(
+                Severity::Error,
+                format!(
+                    "Polyident {} is not in the destruct pattern, so it has no values",
+                    u.name
+                ),
+                u.span,
+            )
+        } else {
+            Diagnostic::new(
+                Severity::Error,
+                format!("Identifier {} is not bound by the destruct pattern", u.name),
+                u.span,
+            )
+        });
+    }
+
+    for (i, b) in bound.iter().enumerate() {
+        if !first(&bound, i) {
+            continue;
+        }
+        let constructed = count(&used, b);
+        if b.poly && constructed == 0 {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Error,
+                    format!(
+                        "Polyident {} is never used in the construct pattern, so its values are left over",
+                        b.name
+                    ),
+                    b.span,
+                )
+                .with_note("use `_` to ignore values instead"),
+            );
+        } else if b.poly
+            && constructed != count(&bound, b)
+            && !bound
+                .iter()
+                .chain(&used)
+                .any(|v| v.name == b.name && v.poly && v.repeated)
+        {
+            let u = used.iter().find(|u| u.name == b.name && u.poly).unwrap();
+            diagnostics.push(Diagnostic::new(
+                Severity::Error,
+                format!(
+                    "Polyident {} is destructed {} times but constructed {} times, so its values don't line up",
+                    b.name,
+                    count(&bound, b),
+                    constructed
+                ),
+                u.span,
+            ));
+        } else if !b.poly && constructed == 0 && !b.name.starts_with('_') {
+            diagnostics.push(
+                Diagnostic::new(
+                    Severity::Warning,
+                    format!(
+                        "Identifier {} is never used, so this transformation can't be reversed",
+                        b.name
+                    ),
+                    b.span,
+                )
+                .with_note("use `_` to ignore a value, or start its name with `_`"),
+            );
+        }
+    }
+    diagnostics.sort_by_key(|d| d.span.start);
+    out.extend(diagnostics);
+}
+
+/// Collects the identifiers in `expr`, in the order they appear
+fn uses(expr: &Expression, repeated: bool, out: &mut Vec<Use>) {
+    match &expr.data {
+        Expr::Ident(name) => out.push(Use {
+            name: *name,
+            poly: false,
+            repeated,
+            span: expr.span,
+        }),
+        Expr::PolyIdent(name) => out.push(Use {
+            name: *name,
+            poly: true,
+            repeated,
+            span: expr.span,
+        }),
+        Expr::Array(items) | Expr::Tuple(items) => {
+            for e in items {
+                uses(e, repeated, out);
+            }
+        }
+        Expr::Map(entries, rest) => {
+            for (_, e) in entries {
+                uses(e, repeated, out);
+            }
+            if let Some(rest) = rest {
+                uses(rest, repeated, out);
+            }
+        }
+        Expr::Format(parts) => {
+            for part in parts {
+                if let FormatPart::Interpolation(e) = part {
+                    uses(e, repeated, out);
+                }
+            }
+        }
+        Expr::Operator(op, left, right) => {
+            let repeated = repeated || matches!(op, Operator::Mul);
+            uses(left, repeated, out);
+            uses(right, repeated, out);
+        }
+        Expr::UnaryOp(_, e) | Expr::Cast(e, _, _) | Expr::Call(_, e) => uses(e, repeated, out),
+        Expr::Number(_) | Expr::Int(_) | Expr::String(_) | Expr::Bool(_) | Expr::Any => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parser::Lexer;
+
+    /// The severity and message of every diagnostic for `source`
+    fn diagnostics(source: &str) -> Vec<(Severity, String)> {
+        check(&Lexer::new(source, None).parse().unwrap())
+            .into_iter()
+            .map(|d| (d.severity, d.message))
+            .collect()
+    }
+
+    fn severities(source: &str) -> Vec<Severity> {
+        diagnostics(source).into_iter().map(|(s, _)| s).collect()
+    }
+
+    #[test]
+    fn matching_patterns_are_fine() {
+        assert!(diagnostics("main := (a, b) -> [b, a];").is_empty());
+        assert!(diagnostics("main := (a, _, _b) -> a;").is_empty());
+        assert!(diagnostics("main := [*a] * n -> [*a * 2] * n;").is_empty());
+    }
+
+    #[test]
+    fn unbound_identifiers_are_errors() {
+        assert_eq!(
+            diagnostics("main := (a, b) -> c;"),
+            // in the order they are in the source
+            [
+                (
+                    Severity::Warning,
+                    "Identifier a is never used, so this transformation can't be reversed"
+                        .to_string()
+                ),
+                (
+                    Severity::Warning,
+                    "Identifier b is never used, so this transformation can't be reversed"
+                        .to_string()
+                ),
+                (
+                    Severity::Error,
+                    "Identifier c is not bound by the destruct pattern".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_identifiers_are_warnings() {
+        assert_eq!(severities("main := (a, b) -> a;"), [Severity::Warning]);
+        // every branch is checked on its own
+        assert_eq!(
+            severities("main := ? (a, b) -> a : c -> c;"),
+            [Severity::Warning]
+        );
+    }
+
+    #[test]
+    fn polyidents_must_be_in_both_patterns() {
+        assert_eq!(severities("main := [*a] -> 1;"), [Severity::Error]);
+        assert_eq!(severities("main := _ -> [*a];"), [Severity::Error]);
+    }
+
+    #[test]
+    fn unrepeated_polyidents_must_be_used_as_often_as_bound() {
+        assert_eq!(
+            severities("main := [*a] -> ([*a], [*a]);"),
+            [Severity::Error]
+        );
+        assert_eq!(
+            severities("main := ([*a], [*a]) -> [*a];"),
+            [Severity::Error]
+        );
+        assert!(diagnostics("main := [*a, *b] -> [*b, *a];").is_empty());
+        // a repetition can match it any number of times
+        assert!(
+            diagnostics("main := ([*a] * n, i) -> ([*a] * i, *a, [*a] * (n - i - 1));").is_empty()
+        );
+    }
+}
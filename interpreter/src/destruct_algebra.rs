@@ -1,12 +1,17 @@
 use crate::{
     error::RuntimeError,
-    traits::{Functions, Structure, Value, Variables},
+    traits::{
+        DestructResult, Functions, PartialStringPart, PartialValue, Structure, Value, Variables,
+    },
+};
+use parser::{
+    ast::{Expr, Expression, FormatPart, Type},
+    parser::Sp,
 };
-use parser::ast::Expr;
 
 pub fn add_left_destruct(
     left: &Value,
-    right: &Expr,
+    right: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -18,29 +23,34 @@ pub fn add_left_destruct(
         // s1 + x = s2
         (Value::String(s1), Value::String(s2)) => {
             if !s2.starts_with(s1) {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Expected {} to start with {}",
-                    s2, s1
-                )));
+                return Err(variables.mismatch(|| format!("Expected {} to start with {}", s2, s1)));
             }
             Value::String(s2[s1.len()..].to_string())
         }
         // a1 + x = a2
         (Value::Array(a1), Value::Array(a2)) => {
             if !a2.starts_with(a1) {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Expected {} to start with {}",
-                    Value::Array(a2.clone()),
-                    Value::Array(a1.clone())
-                )));
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Expected {} to start with {}",
+                        Value::Array(a2.clone()),
+                        Value::Array(a1.clone())
+                    )
+                }));
             }
             Value::Array(a2[a1.len()..].to_vec())
         }
+        // t1 + x = t2
+        (Value::Tuple(t1), Value::Tuple(t2)) => {
+            if !t2.starts_with(t1) {
+                return Err(variables
+                    .mismatch(|| format!("Expected {} to start with {}", target_val, left)));
+            }
+            Value::Tuple(t2[t1.len()..].to_vec())
+        }
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot add {} with something to get {}",
-                left, target_val
-            )))
+            return Err(variables
+                .mismatch(|| format!("Cannot add {} with something to get {}", left, target_val)))
         }
     };
     right.destruct(&target_val, variables, functions)?;
@@ -49,7 +59,7 @@ pub fn add_left_destruct(
 
 pub fn add_right_destruct(
     right: &Value,
-    left: &Expr,
+    left: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -60,29 +70,35 @@ pub fn add_right_destruct(
         // x + s1 = s2
         (Value::String(s1), Value::String(s2)) => {
             if !s2.ends_with(s1) {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Expected {} to end with {}",
-                    s2, s1
-                )));
+                return Err(variables.mismatch(|| format!("Expected {} to end with {}", s2, s1)));
             }
             Value::String(s2[..(s2.len() - s1.len())].to_string())
         }
         // x + a1 = a2
         (Value::Array(a1), Value::Array(a2)) => {
             if !a2.ends_with(a1) {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Expected {} to end with {}",
-                    Value::Array(a2.clone()),
-                    Value::Array(a1.clone())
-                )));
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Expected {} to end with {}",
+                        Value::Array(a2.clone()),
+                        Value::Array(a1.clone())
+                    )
+                }));
             }
             Value::Array(a2[..(a2.len() - a1.len())].to_vec())
         }
+        // x + t1 = t2
+        (Value::Tuple(t1), Value::Tuple(t2)) => {
+            if !t2.ends_with(t1) {
+                return Err(
+                    variables.mismatch(|| format!("Expected {} to end with {}", target_val, right))
+                );
+            }
+            Value::Tuple(t2[..(t2.len() - t1.len())].to_vec())
+        }
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot add something with {} to get {}",
-                right, target_val
-            )))
+            return Err(variables
+                .mismatch(|| format!("Cannot add something with {} to get {}", right, target_val)))
         }
     };
 
@@ -92,7 +108,7 @@ pub fn add_right_destruct(
 
 pub fn sub_left_destruct(
     left: &Value,
-    right: &Expr,
+    right: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -102,10 +118,12 @@ pub fn sub_left_destruct(
         (Value::Number(n1), Value::Number(n2)) => Value::Number(n1 - n2),
 
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot subtract {} from something to get {}",
-                left, target_val
-            )))
+            return Err(variables.mismatch(|| {
+                format!(
+                    "Cannot subtract {} from something to get {}",
+                    left, target_val
+                )
+            }))
         }
     };
     right.destruct(&target_val, variables, functions)?;
@@ -114,7 +132,7 @@ pub fn sub_left_destruct(
 
 pub fn sub_right_destruct(
     right: &Value,
-    left: &Expr,
+    left: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -124,10 +142,12 @@ pub fn sub_right_destruct(
         (Value::Number(n1), Value::Number(n2)) => Value::Number(n1 + n2),
 
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot subtract something from {} to get {}",
-                right, target_val
-            )))
+            return Err(variables.mismatch(|| {
+                format!(
+                    "Cannot subtract something from {} to get {}",
+                    right, target_val
+                )
+            }))
         }
     };
 
@@ -137,53 +157,74 @@ pub fn sub_right_destruct(
 
 pub fn mul_left_destruct(
     left: &Value,
-    right: &Expr,
+    right: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
 ) -> Result<(), RuntimeError> {
+    // an empty value repeated any number of times is still empty, so there is no count to find
+    if matches!(left, Value::Array(a) | Value::Tuple(a) if a.is_empty())
+        || matches!(left, Value::String(s) if s.is_empty())
+    {
+        return Err(variables.mismatch(|| {
+            format!(
+                "Cannot tell how many times {} was repeated to get {}",
+                left, target_val
+            )
+        }));
+    }
     let target_val = match (left, target_val) {
         // n1 * x = n2
-        (Value::Number(n1), Value::Number(n2)) => Value::Number(n2 / n1),
-        (Value::Array(a1), Value::Array(a2)) => {
+        (Value::Number(n1), Value::Number(n2)) => {
+            Value::Number(n2.exact_div(n1).ok_or_else(|| {
+                variables
+                    .mismatch(|| format!("Cannot multiply {} with an integer to get {}", n1, n2))
+            })?)
+        }
+        (Value::Array(a1), Value::Array(a2)) | (Value::Tuple(a1), Value::Tuple(a2)) => {
             if a2.len() % a1.len() != 0 {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Length of target array {} is not divisible by length of destruct array {}",
-                    Value::Array(a2.clone()),
-                    Value::Array(a1.clone())
-                )));
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Length of target array {} is not divisible by length of destruct array {}",
+                        target_val, left
+                    )
+                }));
             }
             let repeats = a2.len() / a1.len();
             for (i, el) in a2.iter().enumerate() {
                 if a1[i % a1.len()] != *el {
-                    return Err(RuntimeError::PatternMismatch(format!("Element {} at index {} of target array {} does not match element {} at index {} of destruct array {}", el, i, Value::Array(a2.clone()), a1[i % a1.len()], i % a1.len(), Value::Array(a1.clone()))));
+                    return Err(variables.mismatch(|| format!("Element {} at index {} of target array {} does not match element {} at index {} of destruct array {}", el, i, target_val, a1[i % a1.len()], i % a1.len(), left)));
                 }
             }
 
-            Value::Number(repeats as f64)
+            Value::Number(repeats.into())
         }
         (Value::String(s1), Value::String(s2)) => {
             if s2.len() % s1.len() != 0 {
-                return Err(RuntimeError::PatternMismatch(format!(
+                return Err(variables.mismatch(|| {
+                    format!(
                     "Length of target string {} is not divisible by length of destruct string {}",
                     s2, s1
-                )));
+                )
+                }));
             }
             let repeats = s2.len() / s1.len();
             for (i, c) in s2.bytes().enumerate() {
                 // since .len() is the bytes
                 if s1.as_bytes()[i % s1.len()] != c {
-                    return Err(RuntimeError::PatternMismatch(format!("Character {} at index {} of target string {} does not match character {} at index {} of destruct string {}", c as char, i, s2, s1.as_bytes()[i % s1.len()] as char, i % s1.len(), s1)));
+                    return Err(variables.mismatch(|| format!("Character {} at index {} of target string {} does not match character {} at index {} of destruct string {}", c as char, i, s2, s1.as_bytes()[i % s1.len()] as char, i % s1.len(), s1)));
                 }
             }
-            Value::Number(repeats as f64)
+            Value::Number(repeats.into())
         }
 
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot multiply {} with something to get {}",
-                left, target_val
-            )))
+            return Err(variables.mismatch(|| {
+                format!(
+                    "Cannot multiply {} with something to get {}",
+                    left, target_val
+                )
+            }))
         }
     };
     right.destruct(&target_val, variables, functions)?;
@@ -192,7 +233,7 @@ pub fn mul_left_destruct(
 
 pub fn mul_right_destruct(
     right: &Value,
-    left: &Expr,
+    left: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -200,36 +241,46 @@ pub fn mul_right_destruct(
     match (right, target_val) {
         // x * n1 = n2
         (Value::Number(n1), Value::Number(n2)) => {
-            let target_val = Value::Number(n2 / n1);
+            let target_val = Value::Number(n2.exact_div(n1).ok_or_else(|| {
+                variables
+                    .mismatch(|| format!("Cannot multiply an integer with {} to get {}", n1, n2))
+            })?);
             left.destruct(&target_val, variables, functions)?;
             Ok(())
         }
-        (Value::Number(n1), Value::Array(a2)) => {
-            if n1.fract() != 0.0 {
-                return Err(RuntimeError::ValueError(format!(
-                    "Cannot multiply array with non-integer number {}",
-                    n1
-                )));
+        (Value::Number(n1), Value::Array(_) | Value::Tuple(_)) => {
+            let (a2, kind) = sequence(target_val).unwrap();
+            let n = n1.to_count().ok_or_else(|| {
+                RuntimeError::ValueError(
+                    format!("Cannot multiply array with {}, only with whole numbers", n1),
+                    None,
+                )
+            })?;
+            if n == 0 {
+                return Err(variables
+                    .mismatch(|| format!("Cannot multiply array with 0 to get {}", target_val)));
             }
-            let n = *n1 as usize;
             if n > a2.len() {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Cannot multiply array with number {} greater than length of array {}",
-                    n,
-                    a2.len()
-                )));
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Cannot multiply array with number {} greater than length of array {}",
+                        n,
+                        a2.len()
+                    )
+                }));
             }
             if a2.len() % n != 0 {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Length of array {} is not divisible by number {}",
-                    Value::Array(a2.clone()),
-                    n
-                )));
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Length of array {} is not divisible by number {}",
+                        target_val, n
+                    )
+                }));
             }
             let len = a2.len() / n;
 
             for i in 0..n {
-                let target = &Value::Array(a2[i * len..(i + 1) * len].to_vec());
+                let target = &kind(a2[i * len..(i + 1) * len].to_vec());
                 left.destruct(target, variables, functions)?;
             }
 
@@ -237,55 +288,66 @@ pub fn mul_right_destruct(
         }
 
         (Value::Number(n1), Value::String(s2)) => {
-            if n1.fract() != 0.0 {
-                return Err(RuntimeError::ValueError(format!(
-                    "Cannot multiply string with non-integer number {}",
-                    n1
-                )));
+            let n = n1.to_count().ok_or_else(|| {
+                RuntimeError::ValueError(
+                    format!(
+                        "Cannot multiply string with {}, only with whole numbers",
+                        n1
+                    ),
+                    None,
+                )
+            })?;
+            if n == 0 {
+                return Err(variables
+                    .mismatch(|| format!("Cannot multiply string with 0 to get {}", target_val)));
             }
-            let n = *n1 as usize;
-            if n > s2.len() {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Cannot multiply string with number {} greater than length of string {}",
-                    n,
-                    s2.len()
-                )));
+            // split by characters, as byte offsets could fall inside one
+            let chars = s2.chars().collect::<Vec<_>>();
+            if n > chars.len() {
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Cannot multiply string with number {} greater than length of string {}",
+                        n,
+                        chars.len()
+                    )
+                }));
             }
-            if s2.len() % n != 0 {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Length of string {} is not divisible by number {}",
-                    s2, n
-                )));
+            if chars.len() % n != 0 {
+                return Err(variables.mismatch(|| {
+                    format!("Length of string {} is not divisible by number {}", s2, n)
+                }));
             }
-            let len = s2.len() / n;
+            let len = chars.len() / n;
 
             for i in 0..n {
-                let target = &Value::String(s2[i * len..(i + 1) * len].to_string());
+                let target = &Value::String(chars[i * len..(i + 1) * len].iter().collect());
                 left.destruct(target, variables, functions)?;
             }
 
             Ok(())
         }
 
-        _ => {
-            return Err(RuntimeError::ValueError(format!(
+        _ => Err(variables.mismatch(|| {
+            format!(
                 "Cannot multiply something with {} to get {}",
                 right, target_val
-            )))
-        }
+            )
+        })),
     }
 }
 
 pub fn div_left_destruct(
     left: &Value,
-    right: &Expr,
+    right: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
 ) -> Result<(), RuntimeError> {
     let target_val = match (left, target_val) {
         // n1 / x = n2
-        (Value::Number(n1), Value::Number(n2)) => Value::Number(n1 / n2),
+        (Value::Number(n1), Value::Number(n2)) => Value::Number(n1.div(n2).ok_or_else(|| {
+            variables.mismatch(|| format!("Cannot divide {} by something to get {}", n1, n2))
+        })?),
 
         (Value::String(s1), Value::Array(arr)) => {
             // arr is a list of substrings in the order they appear in in s1
@@ -298,50 +360,63 @@ pub fn div_left_destruct(
                 match el {
                     Value::String(s) => strings.push(s.clone()),
                     a => {
-                        return Err(RuntimeError::ValueError(format!(
-                            "Cannot divide string by array containing non-string {}",
-                            a
-                        )))
+                        return Err(variables.mismatch(|| {
+                            format!("Cannot divide string by array containing non-string {}", a)
+                        }))
                     }
                 }
             }
 
+            if strings.len() < 2 {
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Cannot tell what {} was split by from fewer than two pieces {}",
+                        Value::String(s1.clone()),
+                        Value::Array(arr.clone())
+                    )
+                }));
+            }
+
             if !s1.starts_with(&strings[0]) {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "First element of array {} is not a prefix of string {}",
-                    Value::Array(arr.clone()),
-                    Value::String(s1.clone())
-                )));
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "First element of array {} is not a prefix of string {}",
+                        Value::Array(arr.clone()),
+                        Value::String(s1.clone())
+                    )
+                }));
             }
 
             let rest = &s1[strings[0].len()..];
 
             // find the second element of the array in the `rest` string
-            let mut i = 0;
-            while i < rest.len() {
-                if rest[i..].starts_with(&strings[1]) {
-                    break;
-                }
-                i += 1;
-            }
+            let i = rest
+                .char_indices()
+                .map(|(i, _)| i)
+                .find(|&i| rest[i..].starts_with(&strings[1]))
+                .unwrap_or(rest.len());
             let delim = &rest[0..i];
 
             if &strings.join(delim) != s1 {
-                return Err(RuntimeError::PatternMismatch(format!(
-                    "Cannot find delimiter that fits between array {} and string {}",
-                    Value::Array(arr.clone()),
-                    Value::String(s1.clone())
-                )));
+                return Err(variables.mismatch(|| {
+                    format!(
+                        "Cannot find delimiter that fits between array {} and string {}",
+                        Value::Array(arr.clone()),
+                        Value::String(s1.clone())
+                    )
+                }));
             }
 
             Value::String(delim.to_string())
         }
 
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot divide {} with something to get {}",
-                left, target_val
-            )))
+            return Err(variables.mismatch(|| {
+                format!(
+                    "Cannot divide {} with something to get {}",
+                    left, target_val
+                )
+            }))
         }
     };
     right.destruct(&target_val, variables, functions)?;
@@ -350,7 +425,7 @@ pub fn div_left_destruct(
 
 pub fn div_right_destruct(
     right: &Value,
-    left: &Expr,
+    left: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -366,10 +441,9 @@ pub fn div_right_destruct(
                 match el {
                     Value::String(s) => strings.push(s.clone()),
                     a => {
-                        return Err(RuntimeError::ValueError(format!(
-                            "Cannot divide string by array containing non-string {}",
-                            a
-                        )))
+                        return Err(variables.mismatch(|| {
+                            format!("Cannot divide string by array containing non-string {}", a)
+                        }))
                     }
                 }
             }
@@ -378,10 +452,12 @@ pub fn div_right_destruct(
         }
 
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot divide something with {} to get {}",
-                right, target_val
-            )))
+            return Err(variables.mismatch(|| {
+                format!(
+                    "Cannot divide something with {} to get {}",
+                    right, target_val
+                )
+            }))
         }
     };
 
@@ -391,7 +467,7 @@ pub fn div_right_destruct(
 
 pub fn and_destruct(
     val: &Value,
-    expr: &Expr,
+    expr: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -405,18 +481,14 @@ pub fn and_destruct(
             expr.destruct(&Value::Bool(false), variables, functions)?;
             Ok(())
         }
-        _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot && {} with something to get {}",
-                val, target_val
-            )))
-        }
+        _ => Err(variables
+            .mismatch(|| format!("Cannot && {} with something to get {}", val, target_val))),
     }
 }
 
 pub fn or_destruct(
     val: &Value,
-    expr: &Expr,
+    expr: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -432,19 +504,16 @@ pub fn or_destruct(
         }
         (Value::Bool(true), Value::Bool(true)) => Err(RuntimeError::ValueError(
             "Cannot destruct variable that can be either true or false".to_string(),
+            None,
         )),
-        _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot || {} with something to get {}",
-                val, target_val
-            )))
-        }
+        _ => Err(variables
+            .mismatch(|| format!("Cannot || {} with something to get {}", val, target_val))),
     }
 }
 
 pub fn eq_destruct(
     val: &Value,
-    expr: &Expr,
+    expr: &Expression,
     target_val: &Value,
     variables: &mut Variables,
     functions: &Functions,
@@ -454,15 +523,407 @@ pub fn eq_destruct(
             expr.destruct(val, variables, functions)?;
             Ok(())
         }
-        Value::Bool(false) => Err(RuntimeError::ValueError(format!(
-            "Cannot destruct variable that can be any value other than {}",
-            val,
-        ))),
+        Value::Bool(false) => Err(RuntimeError::ValueError(
+            format!(
+                "Cannot destruct variable that can be any value other than {}",
+                val,
+            ),
+            None,
+        )),
+        _ => Err(variables
+            .mismatch(|| format!("Cannot == {} with something to get {}", val, target_val))),
+    }
+}
+
+fn two_unknowns() -> RuntimeError {
+    RuntimeError::ValueError(
+        "Cannot destruct expression with two unknowns".to_string(),
+        None,
+    )
+}
+
+/// x + y = v, for strings, arrays and tuples, trying every way to split `v` with the shortest `x` first
+pub fn add_split_destruct(
+    left: &Expression,
+    right: &Expression,
+    target_val: &Value,
+    variables: &mut Variables,
+    functions: &Functions,
+) -> Result<(), RuntimeError> {
+    match target_val {
+        Value::String(_) => string_destruct(
+            &[
+                PartialStringPart::Expr(left.clone(), None),
+                PartialStringPart::Expr(right.clone(), None),
+            ],
+            target_val,
+            variables,
+            functions,
+        ),
+        _ => {
+            let (items, kind) = sequence(target_val).ok_or_else(two_unknowns)?;
+            let split = variables.choices.choose(items.len() + 1);
+            left.destruct(&kind(items[..split].to_vec()), variables, functions)?;
+            right.destruct(&kind(items[split..].to_vec()), variables, functions)?;
+            Ok(())
+        }
+    }
+}
+
+/// x * n = v, for strings, arrays and tuples, trying every piece that `v` is made of repeats of with the
+/// shortest piece first. An empty `v` is one empty piece.
+pub fn mul_split_destruct(
+    left: &Expression,
+    right: &Expression,
+    target_val: &Value,
+    variables: &mut Variables,
+    functions: &Functions,
+) -> Result<(), RuntimeError> {
+    let (items, kind) = match target_val {
+        Value::String(s) => (
+            s.chars().map(|c| Value::String(c.to_string())).collect(),
+            Value::Array as SequenceKind,
+        ),
         _ => {
-            return Err(RuntimeError::ValueError(format!(
-                "Cannot == {} with something to get {}",
-                val, target_val
-            )))
+            let (items, kind) = sequence(target_val).ok_or_else(two_unknowns)?;
+            (items.to_vec(), kind)
+        }
+    };
+    let mut lengths = (1..=items.len())
+        .filter(|&n| items.len() % n == 0 && items.chunks(n).all(|c| c == &items[..n]))
+        .collect::<Vec<_>>();
+    if items.is_empty() {
+        lengths.push(0);
+    }
+    let n = lengths[variables.choices.choose(lengths.len())];
+    let piece = match target_val {
+        Value::String(s) => Value::String(s.chars().take(n).collect()),
+        _ => kind(items[..n].to_vec()),
+    };
+    let times = items.len().checked_div(n).unwrap_or(1);
+    left.destruct(&piece, variables, functions)?;
+    right.destruct(&Value::Number(times.into()), variables, functions)?;
+    Ok(())
+}
+
+/// `[c] + rest = s`, where an array or tuple pattern with a known number of elements matches
+/// that many characters, as if it was cast to a string like `[c]::#array~>#string`
+pub fn chars_destruct(
+    left: (&Expression, Option<usize>),
+    right: (&Expression, Option<usize>),
+    target_val: &Value,
+    variables: &mut Variables,
+    functions: &Functions,
+) -> Result<(), RuntimeError> {
+    let part = |(e, len): (&Expression, Option<usize>)| match len {
+        Some(n) => {
+            let from = match e.data {
+                Expr::Tuple(_) => Type::Tuple,
+                _ => Type::Array,
+            };
+            let cast = Expr::Cast(Box::new(e.clone()), Type::String, from);
+            PartialStringPart::Expr(Sp::new(cast, e.span), Some(n))
         }
+        None => PartialStringPart::Expr(e.clone(), None),
+    };
+    string_destruct(&[part(left), part(right)], target_val, variables, functions)
+}
+
+/// Makes an array or a tuple out of elements
+pub type SequenceKind = fn(Vec<Value>) -> Value;
+
+/// The elements of an array or tuple, with how to make another value of the same kind
+pub fn sequence(value: &Value) -> Option<(&[Value], SequenceKind)> {
+    match value {
+        Value::Array(a) => Some((a, Value::Array)),
+        Value::Tuple(t) => Some((t, Value::Tuple)),
+        _ => None,
+    }
+}
+
+/// The parts of the string `expr` stands for, given what destructuring it to a value gave
+pub fn string_parts(
+    result: &DestructResult,
+    expr: &Expression,
+    functions: &Functions,
+    variables: &Variables,
+) -> Result<Vec<PartialStringPart>, RuntimeError> {
+    match result {
+        DestructResult::Known(Value::String(s))
+        | DestructResult::Partial(PartialValue::Value(Value::String(s))) => {
+            Ok(vec![PartialStringPart::String(s.clone())])
+        }
+        DestructResult::Known(v) | DestructResult::Partial(PartialValue::Value(v)) => {
+            Err(RuntimeError::TypeMismatch(
+                "string".to_string(),
+                v.to_type().to_string(),
+                Some(expr.span),
+            ))
+        }
+        DestructResult::Partial(PartialValue::String(parts)) => Ok(parts.clone()),
+        DestructResult::Partial(PartialValue::Array { .. }) | DestructResult::Unknown => {
+            Ok(vec![PartialStringPart::Expr(
+                expr.clone(),
+                char_len(expr, functions, variables)?,
+            )])
+        }
+    }
+}
+
+/// The parts of `left + right` if it is sure to be a string that isn't fully known, because
+/// some of its text is known or some unknown part has a fixed number of characters
+pub fn string_sum(
+    sides: &(DestructResult, DestructResult),
+    left: &Expression,
+    right: &Expression,
+    functions: &Functions,
+    variables: &Variables,
+) -> Result<Option<Vec<PartialStringPart>>, RuntimeError> {
+    use DestructResult::*;
+    let is_string = |r: &DestructResult| {
+        matches!(
+            r,
+            Known(Value::String(_))
+                | Partial(PartialValue::Value(Value::String(_)) | PartialValue::String(_))
+                | Unknown
+        )
+    };
+    let known = |r: &DestructResult| matches!(r, Known(_) | Partial(PartialValue::Value(_)));
+    let (l, r) = sides;
+    if !is_string(l) || !is_string(r) || (known(l) && known(r)) {
+        return Ok(None);
     }
+    let mut parts = string_parts(l, left, functions, variables)?;
+    append_parts(&mut parts, string_parts(r, right, functions, variables)?);
+    if parts
+        .iter()
+        .all(|p| matches!(p, PartialStringPart::Expr(_, None)))
+    {
+        return Ok(None);
+    }
+    Ok(Some(parts))
+}
+
+/// How many characters the string `expr` matches, if that is fixed, like the one character of
+/// `[c]::#array~>#string`
+fn char_len(
+    expr: &Expression,
+    functions: &Functions,
+    variables: &Variables,
+) -> Result<Option<usize>, RuntimeError> {
+    Ok(match &expr.data {
+        Expr::Cast(inner, Type::String, Type::Array | Type::Tuple) => match &inner.data {
+            Expr::Tuple(t) => Some(t.len()),
+            _ => match inner.destruct_to_value(functions, variables)? {
+                DestructResult::Partial(PartialValue::Array { len, .. }) => len,
+                _ => None,
+            },
+        },
+        _ => None,
+    })
+}
+
+/// Appends `more` to `parts`, joining text that ends up next to each other
+pub fn append_parts(parts: &mut Vec<PartialStringPart>, more: Vec<PartialStringPart>) {
+    for part in more {
+        match (parts.last_mut(), part) {
+            (Some(PartialStringPart::String(a)), PartialStringPart::String(b)) => a.push_str(&b),
+            (_, part) => parts.push(part),
+        }
+    }
+}
+
+/// The parts of `f"..."`, where interpolations that are already known are text like the rest
+pub fn format_parts(
+    parts: &[FormatPart],
+    functions: &Functions,
+    variables: &Variables,
+) -> Result<Vec<PartialStringPart>, RuntimeError> {
+    let mut out = Vec::new();
+    for part in parts {
+        let more = match part {
+            FormatPart::Text(t) => vec![PartialStringPart::String(t.clone())],
+            FormatPart::Interpolation(e) => string_parts(
+                &e.destruct_to_value(functions, variables)?,
+                e,
+                functions,
+                variables,
+            )?,
+        };
+        append_parts(&mut out, more);
+    }
+    Ok(out)
+}
+
+/// Byte index of character `n` of `s`, which may be one past the last character
+fn char_index(s: &str, n: usize) -> Option<usize> {
+    s.char_indices().map(|(i, _)| i).chain([s.len()]).nth(n)
+}
+
+/// a + "," + b = s, matching the parts from left to right. A part of unknown length takes the
+/// text up to the first place the text after it appears, or as much as leaves just enough for
+/// the rest of the string if everything after it has a known length.
+pub fn string_destruct(
+    parts: &[PartialStringPart],
+    target_val: &Value,
+    variables: &mut Variables,
+    functions: &Functions,
+) -> Result<(), RuntimeError> {
+    let mut rest = match target_val {
+        Value::String(s) => s.as_str(),
+        v => return Err(variables.mismatch(|| format!("Expected string, got {}", v))),
+    };
+    let too_short = |variables: &Variables, rest: &str, n: usize| {
+        variables.mismatch(|| format!("Expected {:?} to have at least {} characters", rest, n))
+    };
+    for (i, part) in parts.iter().enumerate() {
+        let (e, len) = match part {
+            PartialStringPart::String(t) => {
+                rest = rest.strip_prefix(t.as_str()).ok_or_else(|| {
+                    variables.mismatch(|| format!("Expected {:?} to start with {:?}", rest, t))
+                })?;
+                continue;
+            }
+            PartialStringPart::Expr(e, len) => (e, len),
+        };
+        let after = &parts[i + 1..];
+        let end = match len {
+            Some(n) => char_index(rest, *n).ok_or_else(|| too_short(variables, rest, *n))?,
+            None if after
+                .iter()
+                .all(|p| !matches!(p, PartialStringPart::Expr(_, None))) =>
+            {
+                // everything after has a known length, so this part ends that far from the end
+                let needed = after
+                    .iter()
+                    .map(|p| match p {
+                        PartialStringPart::String(t) => t.chars().count(),
+                        PartialStringPart::Expr(_, n) => n.unwrap(),
+                    })
+                    .sum::<usize>();
+                let total = rest.chars().count();
+                if total < needed {
+                    return Err(too_short(variables, rest, needed));
+                }
+                char_index(rest, total - needed).unwrap()
+            }
+            None => {
+                // where whatever follows the parts of known length right after this one can start
+                let fixed = after
+                    .iter()
+                    .map_while(|p| match p {
+                        PartialStringPart::Expr(_, n) => *n,
+                        PartialStringPart::String(_) => None,
+                    })
+                    .collect::<Vec<_>>();
+                let skip = fixed.iter().sum::<usize>();
+                let from =
+                    char_index(rest, skip).ok_or_else(|| too_short(variables, rest, skip))?;
+                let starts = rest[from..]
+                    .char_indices()
+                    .map(|(i, _)| from + i)
+                    .chain([rest.len()]);
+                let starts = match &after[fixed.len()] {
+                    PartialStringPart::String(t) => {
+                        let starts = starts
+                            .filter(|&i| rest[i..].starts_with(t.as_str()))
+                            .collect::<Vec<_>>();
+                        if starts.is_empty() {
+                            return Err(variables.mismatch(|| {
+                                format!("Expected {:?} to contain {:?}", &rest[from..], t)
+                            }));
+                        }
+                        starts
+                    }
+                    // anywhere, when two parts of unknown length are next to each other
+                    PartialStringPart::Expr(..) => starts.collect(),
+                };
+                // shortest first
+                let start = starts[variables.choices.choose(starts.len())];
+                char_index(rest, rest[..start].chars().count() - skip).unwrap()
+            }
+        };
+        e.destruct(
+            &Value::String(rest[..end].to_string()),
+            variables,
+            functions,
+        )?;
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        return Err(
+            variables.mismatch(|| format!("Unexpected {:?} at the end of the string", rest))
+        );
+    }
+    Ok(())
+}
+
+/// The parts of a string repeated `n` times
+pub fn repeat_parts(parts: &[PartialStringPart], n: usize) -> Vec<PartialStringPart> {
+    let mut out = Vec::new();
+    for _ in 0..n {
+        append_parts(&mut out, parts.to_vec());
+    }
+    out
+}
+
+/// (a + ",") * n = s, matching the parts once for every repetition. When `n` is unknown, every
+/// number of repetitions that the known text leaves room for is tried, most first, so that
+/// `(c + ",") * n` splits at every comma.
+pub fn string_repeat_destruct(
+    parts: &[PartialStringPart],
+    times: &DestructResult,
+    right: &Expression,
+    target_val: &Value,
+    variables: &mut Variables,
+    functions: &Functions,
+) -> Result<(), RuntimeError> {
+    let s = match target_val {
+        Value::String(s) => s,
+        v => return Err(variables.mismatch(|| format!("Expected string, got {}", v))),
+    };
+    let n = match times {
+        DestructResult::Known(Value::Number(n))
+        | DestructResult::Partial(PartialValue::Value(Value::Number(n))) => {
+            n.to_count().ok_or_else(|| {
+                RuntimeError::ValueError(
+                    format!("Can only repeat a value a whole number of times, not {}", n),
+                    None,
+                )
+            })?
+        }
+        DestructResult::Known(v) | DestructResult::Partial(PartialValue::Value(v)) => {
+            return Err(RuntimeError::TypeMismatch(
+                "number".to_string(),
+                v.to_type().to_string(),
+                Some(right.span),
+            ))
+        }
+        DestructResult::Unknown => {
+            // the characters each repetition takes at least
+            let min = parts
+                .iter()
+                .map(|p| match p {
+                    PartialStringPart::String(t) => t.chars().count(),
+                    PartialStringPart::Expr(_, n) => n.unwrap_or(0),
+                })
+                .sum::<usize>();
+            if min == 0 {
+                return Err(two_unknowns());
+            }
+            // every repetition has all the text in it
+            let most = parts
+                .iter()
+                .filter_map(|p| match p {
+                    PartialStringPart::String(t) => Some(s.matches(t.as_str()).count()),
+                    PartialStringPart::Expr(..) => None,
+                })
+                .fold(s.chars().count() / min, usize::min);
+            let n = most - variables.choices.choose(most + 1);
+            right.destruct(&Value::Number(n.into()), variables, functions)?;
+            n
+        }
+        DestructResult::Partial(_) => return Err(two_unknowns()),
+    };
+    string_destruct(&repeat_parts(parts, n), target_val, variables, functions)
 }
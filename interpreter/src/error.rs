@@ -1,20 +1,262 @@
+use crate::traits::Value;
+use parser::{internment::LocalIntern, parser::Span};
 use std::{error::Error, fmt::Display};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// A function that was running when an error happened
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: LocalIntern<String>,
+    /// Index of the failing transformation in the function's `|` chain
+    pub transformation: usize,
+    pub direction: Direction,
+    /// The value the transformation was destructing
+    pub value: Value,
+    /// The call expression, `None` for the entry point
+    pub call: Option<Span>,
+}
+
+impl Frame {
+    pub fn new(
+        function: LocalIntern<String>,
+        transformation: usize,
+        direction: Direction,
+        value: Value,
+    ) -> Self {
+        Self {
+            function,
+            transformation,
+            direction,
+            value,
+            call: None,
+        }
+    }
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let direction = match self.direction {
+            Direction::Forward => "forward",
+            Direction::Reverse => "reverse",
+        };
+        write!(
+            f,
+            "`{}`, transformation {} ({}), destructing {}",
+            self.function, self.transformation, direction, self.value
+        )
+    }
+}
+
+/// A branch of a conditional transformation, and why it did not match
+#[derive(Debug, Clone)]
+pub struct Branch {
+    /// The pattern that was destructed
+    pub pattern: Span,
+    pub error: RuntimeError,
+}
+
+/// The last field of every variant is the span of the pattern the error happened in, if known
 #[derive(Debug, Clone)]
 pub enum RuntimeError {
-    PatternMismatch(String),
-    ValueError(String),
-    TypeMismatch(String, String),
+    PatternMismatch(String, Option<Span>),
+    ValueError(String, Option<Span>),
+    TypeMismatch(String, String, Option<Span>),
+    /// Every branch of a `? ... :? ... : ...` chain failed, in the order they were tried
+    NoBranchMatched(Vec<Branch>, Option<Span>),
+    /// `error` with a secondary label, like the pattern it happened in
+    Context {
+        error: Box<RuntimeError>,
+        label: String,
+        span: Option<Span>,
+    },
+    /// A mismatch raised while constructing, which is a bug in the program rather than a value
+    /// not fitting a pattern, see [`RuntimeError::is_mismatch`]
+    Fatal(Box<RuntimeError>),
+    /// `error` happened while running the function in `frame`
+    Trace {
+        error: Box<RuntimeError>,
+        frame: Box<Frame>,
+    },
+}
+
+impl RuntimeError {
+    /// Span of the innermost error
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RuntimeError::PatternMismatch(_, span)
+            | RuntimeError::ValueError(_, span)
+            | RuntimeError::TypeMismatch(_, _, span)
+            | RuntimeError::NoBranchMatched(_, span) => *span,
+            RuntimeError::Context { error, .. }
+            | RuntimeError::Trace { error, .. }
+            | RuntimeError::Fatal(error) => error.span(),
+        }
+    }
+
+    /// Points the error at `span`, unless it already points at a more specific one
+    pub fn at(self, span: Span) -> Self {
+        match self {
+            RuntimeError::PatternMismatch(m, s) => {
+                RuntimeError::PatternMismatch(m, s.or(Some(span)))
+            }
+            RuntimeError::ValueError(m, s) => RuntimeError::ValueError(m, s.or(Some(span))),
+            RuntimeError::TypeMismatch(from, to, s) => {
+                RuntimeError::TypeMismatch(from, to, s.or(Some(span)))
+            }
+            RuntimeError::NoBranchMatched(branches, s) => {
+                RuntimeError::NoBranchMatched(branches, s.or(Some(span)))
+            }
+            RuntimeError::Context {
+                error,
+                label,
+                span: s,
+            } => RuntimeError::Context {
+                error: Box::new(error.at(span)),
+                label,
+                span: s.or(Some(span)),
+            },
+            RuntimeError::Fatal(error) => RuntimeError::Fatal(Box::new(error.at(span))),
+            // the first span the trace gets pointed at is the call expression
+            RuntimeError::Trace { error, mut frame } => {
+                frame.call = frame.call.or(Some(span));
+                RuntimeError::Trace {
+                    error: Box::new(error.at(span)),
+                    frame,
+                }
+            }
+        }
+    }
+
+    /// Adds a secondary label, the span is filled in by [`RuntimeError::at`] if it is `None`
+    pub fn context(self, label: String, span: Option<Span>) -> Self {
+        RuntimeError::Context {
+            error: Box::new(self),
+            label,
+            span,
+        }
+    }
+
+    /// Whether a conditional transformation falls through to its next branch on this error.
+    ///
+    /// Only a value not fitting the destructuring pattern is a mismatch: a [`PatternMismatch`],
+    /// a [`TypeMismatch`] of a cast in the pattern, or a conditional transformation in the
+    /// pattern where no branch matched. The same applies to functions called in the pattern,
+    /// whether they run forward or in reverse. Everything raised while constructing, including
+    /// mismatches in functions called by the constructing pattern, is [`Fatal`], and a
+    /// [`ValueError`] always is, since it means the program itself is wrong.
+    ///
+    /// [`PatternMismatch`]: RuntimeError::PatternMismatch
+    /// [`TypeMismatch`]: RuntimeError::TypeMismatch
+    /// [`Fatal`]: RuntimeError::Fatal
+    /// [`ValueError`]: RuntimeError::ValueError
+    pub fn is_mismatch(&self) -> bool {
+        match self {
+            RuntimeError::PatternMismatch(..)
+            | RuntimeError::TypeMismatch(..)
+            | RuntimeError::NoBranchMatched(..) => true,
+            RuntimeError::ValueError(..) | RuntimeError::Fatal(_) => false,
+            RuntimeError::Context { error, .. } | RuntimeError::Trace { error, .. } => {
+                error.is_mismatch()
+            }
+        }
+    }
+
+    /// Marks a mismatch raised while constructing as fatal
+    pub fn fatal(self) -> Self {
+        if self.is_mismatch() {
+            RuntimeError::Fatal(Box::new(self))
+        } else {
+            self
+        }
+    }
+
+    pub fn in_frame(self, frame: Frame) -> Self {
+        RuntimeError::Trace {
+            error: Box::new(self),
+            frame: Box::new(frame),
+        }
+    }
+
+    /// The error without any of its context
+    pub fn root(&self) -> &RuntimeError {
+        match self {
+            RuntimeError::Context { error, .. }
+            | RuntimeError::Trace { error, .. }
+            | RuntimeError::Fatal(error) => error.root(),
+            e => e,
+        }
+    }
+
+    /// The functions that were running, from the innermost to the outermost
+    pub fn backtrace(&self) -> Vec<&Frame> {
+        match self {
+            RuntimeError::Context { error, .. } | RuntimeError::Fatal(error) => error.backtrace(),
+            RuntimeError::Trace { error, frame } => {
+                let mut frames = error.backtrace();
+                frames.push(frame);
+                frames
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Secondary labels from the innermost to the outermost, including the call of every frame
+    pub fn labels(&self) -> Vec<(Span, String)> {
+        match self {
+            RuntimeError::Context { error, label, span } => {
+                let mut labels = error.labels();
+                if let Some(span) = span {
+                    labels.push((*span, label.clone()));
+                }
+                labels
+            }
+            RuntimeError::Trace { error, frame } => {
+                let mut labels = error.labels();
+                if let Some(span) = frame.call {
+                    let label = match frame.direction {
+                        Direction::Forward => format!("`{}` called from here", frame.function),
+                        Direction::Reverse => {
+                            format!("`{}` run in reverse from here", frame.function)
+                        }
+                    };
+                    labels.push((span, label));
+                }
+                labels
+            }
+            RuntimeError::Fatal(error) => error.labels(),
+            RuntimeError::NoBranchMatched(branches, _) => branches
+                .iter()
+                .enumerate()
+                .map(|(i, b)| (b.pattern, format!("branch {}: {}", i, b.error)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
 }
 
 impl Display for RuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            RuntimeError::PatternMismatch(t) => write!(f, "PATTERN MISSMATCH: {}", t),
-            RuntimeError::ValueError(t) => write!(f, "ERROR: {}", t),
-            RuntimeError::TypeMismatch(from, to) => {
+            RuntimeError::PatternMismatch(t, _) => write!(f, "PATTERN MISSMATCH: {}", t),
+            RuntimeError::ValueError(t, _) => write!(f, "ERROR: {}", t),
+            RuntimeError::TypeMismatch(from, to, _) => {
                 write!(f, "TYPE MISMATCH: cannot convert from {} to {}", from, to)
             }
+            RuntimeError::NoBranchMatched(branches, _) => {
+                write!(f, "NO BRANCH MATCHED:")?;
+                for (i, b) in branches.iter().enumerate() {
+                    write!(f, "\n  branch {}: {}", i, b.error)?;
+                }
+                Ok(())
+            }
+            RuntimeError::Context { error, .. }
+            | RuntimeError::Trace { error, .. }
+            | RuntimeError::Fatal(error) => write!(f, "{}", error),
         }
     }
 }
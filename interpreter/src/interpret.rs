@@ -1,44 +1,85 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-use parser::ast::{Transformation, Type, UnaryOperator};
+use parser::ast::{FormatPart, Transformation, Type, UnaryOperator};
 use parser::internment::LocalIntern;
+use parser::num_bigint::BigInt;
+use parser::parser::{Sp, Span};
 
-use crate::error::RuntimeError;
-use crate::traits::{DestructResult, Functions, Maths, PartialValue, Structure, Value, Variables};
-use parser::ast::Transformation::Forced;
-use parser::ast::{Expr, TopLevel};
+use crate::error::{Branch, Direction, Frame, RuntimeError};
+use crate::number::Number;
+use crate::traits::{
+    Choices, DestructResult, Functions, Maths, PartialStringPart, PartialValue, Structure, Value,
+    Variables,
+};
+use parser::ast::Transformation::{All, Forced};
+use parser::ast::{Expr, Expression, TopLevel};
 
 use crate::destruct_algebra;
 
 impl Value {
-    fn to_type(&self) -> &Type {
+    pub(crate) fn to_type(&self) -> &Type {
         match self {
             Value::String(_) => &Type::String,
             Value::Number(_) => &Type::Number,
             Value::Tuple(_) => &Type::Tuple,
             Value::Array(_) => &Type::Array,
             Value::Bool(_) => &Type::Bool,
+            Value::Map(_) => &Type::Map,
         }
     }
     fn cast(&self, to: &Type, from: &Type) -> Result<Value, RuntimeError> {
-        if from != self.to_type() {
+        // digits in some radix are a string like any other
+        fn as_string(t: &Type) -> &Type {
+            match t {
+                Type::Radix(_) => &Type::String,
+                t => t,
+            }
+        }
+        if as_string(from) != self.to_type() {
             return Err(RuntimeError::TypeMismatch(
                 from.to_string(),
                 self.to_type().to_string(),
+                None,
             ));
         }
+        let parse_radix = |s: &str, radix: u32| {
+            BigInt::parse_bytes(s.as_bytes(), radix).ok_or_else(|| {
+                RuntimeError::ValueError(format!("{:?} is not a {} number", s, from), None)
+            })
+        };
+        match (to, from, self) {
+            (Type::Radix(r), _, Value::Number(Number::Int(i))) => {
+                return Ok(Self::String(i.to_str_radix(*r)))
+            }
+            (Type::Radix(_), _, Value::Number(n)) => {
+                return Err(RuntimeError::ValueError(
+                    format!("Only integers can be converted to {}, not {}", to, n),
+                    None,
+                ))
+            }
+            (Type::Number, Type::Radix(r), Value::String(s)) => {
+                return Ok(Self::Number(Number::Int(parse_radix(s, *r)?)))
+            }
+            (Type::Radix(to_r), Type::Radix(from_r), Value::String(s)) => {
+                return Ok(Self::String(parse_radix(s, *from_r)?.to_str_radix(*to_r)))
+            }
+            _ => (),
+        }
+        let to = as_string(to);
         if to == self.to_type() {
             return Ok(self.clone());
         }
         match (to, self) {
             (Type::Number, Value::String(s)) => {
-                Ok(Self::Number(s.parse::<f64>().ok().unwrap_or(f64::NAN)))
+                Ok(Self::Number(s.parse().unwrap_or(Number::Float(f64::NAN))))
             }
             (Type::Number, Value::Array(_) | Value::Tuple(_)) => Err(RuntimeError::ValueError(
                 "Cannot convert array or tuple to number".to_string(),
+                None,
             )),
             (Type::Array | Type::Tuple, Value::Number(_)) => Err(RuntimeError::ValueError(
                 "Cannot convert number to array or tuple".to_string(),
+                None,
             )),
             (Type::String, v) => Ok(Self::String(format!("{}", v))),
             (Type::Array, Value::String(s)) => Ok(Self::Array(
@@ -47,6 +88,41 @@ impl Value {
             (Type::Tuple, Value::String(s)) => Ok(Self::Tuple(
                 s.chars().map(|x| Value::String(String::from(x))).collect(),
             )),
+            // a map is an array of `(key, value)` entries, in key order
+            (Type::Array, Value::Map(m)) => Ok(Self::Array(
+                m.iter()
+                    .map(|(k, v)| Value::Tuple(vec![Value::String(k.clone()), v.clone()]))
+                    .collect(),
+            )),
+            (Type::Map, Value::Array(a)) => {
+                let mut map = BTreeMap::new();
+                for entry in a {
+                    if let Value::Tuple(t) = entry {
+                        if let [Value::String(k), v] = &t[..] {
+                            if map.insert(k.clone(), v.clone()).is_some() {
+                                return Err(RuntimeError::ValueError(
+                                    format!("Key {:?} appears more than once", k),
+                                    None,
+                                ));
+                            }
+                            continue;
+                        }
+                    }
+                    return Err(RuntimeError::ValueError(
+                        format!("Expected a (string, value) map entry, got {}", entry),
+                        None,
+                    ));
+                }
+                Ok(Self::Map(map))
+            }
+            (Type::Map, v) => Err(RuntimeError::ValueError(
+                format!("Cannot convert {} to map", v.to_type()),
+                None,
+            )),
+            (to, Value::Map(_)) => Err(RuntimeError::ValueError(
+                format!("Cannot convert map to {}", to),
+                None,
+            )),
             // boolean casting?
             _ => unreachable!(),
         }
@@ -61,269 +137,560 @@ impl Maths for Value {
             (Value::Array(lhs), Value::Array(rhs)) => {
                 Ok(Value::Array([lhs.to_owned(), rhs.to_owned()].concat()))
             }
-            (a, b) => Err(RuntimeError::ValueError(format!(
-                "Cannot add {:?} and {:?}",
-                a, b
-            ))),
+            (Value::Tuple(lhs), Value::Tuple(rhs)) => {
+                Ok(Value::Tuple([lhs.to_owned(), rhs.to_owned()].concat()))
+            }
+            (a, b) => Err(RuntimeError::ValueError(
+                format!("Cannot add {:?} and {:?}", a, b),
+                None,
+            )),
         }
     }
 
     fn sub(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
             (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs - rhs)),
-            (a, b) => Err(RuntimeError::ValueError(format!(
-                "Cannot subtract {:?} and {:?}",
-                a, b
-            ))),
+            (a, b) => Err(RuntimeError::ValueError(
+                format!("Cannot subtract {:?} and {:?}", a, b),
+                None,
+            )),
         }
     }
 
     fn div(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
-            (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Number(lhs / rhs)),
+            (Value::Number(lhs), Value::Number(rhs)) => {
+                lhs.div(rhs).map(Value::Number).ok_or_else(|| {
+                    RuntimeError::ValueError(
+                        format!("Cannot divide {} by zero in exact mode", lhs),
+                        None,
+                    )
+                })
+            }
             (Value::String(lhs), Value::String(rhs)) => Ok(Value::Array(
-                lhs.to_owned()
-                    .split(rhs)
+                lhs.split(rhs)
                     .map(|a| Value::String(a.to_string()))
                     .collect(),
             )),
-            _ => Err(RuntimeError::ValueError(format!(
-                "Cannot divide {:?} and {:?}",
-                self, other
-            ))),
+            _ => Err(RuntimeError::ValueError(
+                format!("Cannot divide {:?} and {:?}", self, other),
+                None,
+            )),
         }
     }
 
     fn and(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
             (Value::Bool(lhs), Value::Bool(rhs)) => Ok(Value::Bool(*lhs && *rhs)),
-            _ => Err(RuntimeError::ValueError(format!(
-                "Cannot and {:?} and {:?}",
-                self, other
-            ))),
+            _ => Err(RuntimeError::ValueError(
+                format!("Cannot and {:?} and {:?}", self, other),
+                None,
+            )),
         }
     }
 
     fn or(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
             (Value::Bool(lhs), Value::Bool(rhs)) => Ok(Value::Bool(*lhs || *rhs)),
-            _ => Err(RuntimeError::ValueError(format!(
-                "Cannot or {:?} and {:?}",
-                self, other
-            ))),
+            _ => Err(RuntimeError::ValueError(
+                format!("Cannot or {:?} and {:?}", self, other),
+                None,
+            )),
         }
     }
 
     fn lt_op(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
             (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Bool(lhs < rhs)),
-            (a, b) => Err(RuntimeError::ValueError(format!(
-                "Cannot compare {:?} and {:?}",
-                a, b
-            ))),
+            (a, b) => Err(RuntimeError::ValueError(
+                format!("Cannot compare {:?} and {:?}", a, b),
+                None,
+            )),
         }
     }
 
     fn gt_op(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
             (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Bool(lhs > rhs)),
-            (a, b) => Err(RuntimeError::ValueError(format!(
-                "Cannot compare {:?} and {:?}",
-                a, b
-            ))),
+            (a, b) => Err(RuntimeError::ValueError(
+                format!("Cannot compare {:?} and {:?}", a, b),
+                None,
+            )),
         }
     }
 
     fn le_op(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
             (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Bool(lhs <= rhs)),
-            (a, b) => Err(RuntimeError::ValueError(format!(
-                "Cannot compare {:?} and {:?}",
-                a, b
-            ))),
+            (a, b) => Err(RuntimeError::ValueError(
+                format!("Cannot compare {:?} and {:?}", a, b),
+                None,
+            )),
         }
     }
 
     fn ge_op(&self, other: &Self) -> Result<Value, RuntimeError> {
         match (self, other) {
             (Value::Number(lhs), Value::Number(rhs)) => Ok(Value::Bool(lhs >= rhs)),
-            (a, b) => Err(RuntimeError::ValueError(format!(
-                "Cannot compare {:?} and {:?}",
-                a, b
-            ))),
+            (a, b) => Err(RuntimeError::ValueError(
+                format!("Cannot compare {:?} and {:?}", a, b),
+                None,
+            )),
         }
     }
 }
 
 fn mul(
-    left: &Expr,
+    left: &Expression,
     right: &Value,
     variables: &mut Variables,
     functions: &Functions,
 ) -> Result<Value, RuntimeError> {
-    let factor = *match right {
+    let factor = match right {
         Value::Number(n) => n,
         _ => {
             return Err(RuntimeError::TypeMismatch(
                 "number".to_string(),
                 right.to_type().to_string(),
+                None,
             ))
         }
     };
-    if factor == 0.0 {
+    if factor.is_zero() {
         return match left.destruct_to_value(functions, variables)? {
             DestructResult::Known(a) | DestructResult::Partial(PartialValue::Value(a)) => match a {
-                Value::Number(_) => Ok(Value::Number(0.0)),
+                Value::Number(n) => Ok(Value::Number(&n * factor)),
                 Value::String(_) => Ok(Value::String("".to_string())),
                 Value::Array(_) => Ok(Value::Array(vec![])),
-                a => Err(RuntimeError::ValueError(format!(
-                    "Cannot multiply {:?} by 0",
-                    a
-                ))),
+                Value::Tuple(_) => Ok(Value::Tuple(vec![])),
+                a => Err(RuntimeError::ValueError(
+                    format!("Cannot multiply {:?} by 0", a),
+                    None,
+                )),
             },
+            DestructResult::Partial(PartialValue::Array { tuple: true, .. }) => {
+                Ok(Value::Tuple(vec![]))
+            }
             DestructResult::Partial(PartialValue::Array { .. }) => Ok(Value::Array(vec![])),
+            DestructResult::Partial(PartialValue::String(_)) => Ok(Value::String("".to_string())),
             DestructResult::Unknown => Err(RuntimeError::ValueError(
                 "Cannot unknown value by zero".to_string(),
+                None,
             )),
         };
     }
 
     let mut out = left.construct(variables, functions)?;
     if let Value::Number(n) = out {
-        return Ok(Value::Number(n * factor));
-    }
-    if factor.fract() != 0.0 {
-        return Err(RuntimeError::ValueError(
-            "Can only multiply numbers by fractional number".to_string(),
-        ));
+        return Ok(Value::Number(&n * factor));
     }
-    let n = factor as usize;
+    let n = factor.to_count().ok_or_else(|| {
+        RuntimeError::ValueError(
+            format!(
+                "Can only repeat a value a whole number of times, not {}",
+                factor
+            ),
+            None,
+        )
+    })?;
     for _ in 0..(n - 1) {
         out = out.add(&left.construct(variables, functions)?)?;
     }
     Ok(out)
 }
 
+/// The length of something `len` long repeated `times` times, or an error if that doesn't fit
+/// in memory
+fn repeated_len(len: usize, times: usize) -> Result<usize, RuntimeError> {
+    // no allocation can be bigger than `isize::MAX` bytes
+    let most = isize::MAX as usize / std::mem::size_of::<Value>();
+    len.checked_mul(times)
+        .filter(|&n| n <= most)
+        .ok_or_else(|| {
+            RuntimeError::ValueError(
+                format!("Cannot repeat {} elements {} times", len, times),
+                None,
+            )
+        })
+}
+
 pub fn interpret(top_level: TopLevel, input: Value) -> Result<Value, RuntimeError> {
-    run_func(
-        LocalIntern::new("main".to_string()),
-        input,
-        &top_level.functions,
-    )
+    call(&top_level, LocalIntern::new("main".to_string()), input)
+}
+
+/// Runs `main` in reverse, finding the input it would turn into `output`
+pub fn interpret_reverse(top_level: TopLevel, output: Value) -> Result<Value, RuntimeError> {
+    call_reverse(&top_level, LocalIntern::new("main".to_string()), output)
+}
+
+/// Runs the function `func` on `input`
+pub fn call(
+    top_level: &TopLevel,
+    func: LocalIntern<String>,
+    input: Value,
+) -> Result<Value, RuntimeError> {
+    run_func(func, input, &top_level.functions)
+}
+
+/// Runs the function `func` in reverse, finding the input it would turn into `output`
+pub fn call_reverse(
+    top_level: &TopLevel,
+    func: LocalIntern<String>,
+    output: Value,
+) -> Result<Value, RuntimeError> {
+    reverse_run_func(func, output, &top_level.functions)
+}
+
+/// Runs a single transformation on `value`, like a step of a function
+pub fn apply(
+    top_level: &TopLevel,
+    trans: &Sp<Transformation>,
+    value: &Value,
+) -> Result<Value, RuntimeError> {
+    run_single_transform(trans, value, &top_level.functions).map_err(|e| e.at(trans.span))
+}
+
+/// Runs a single transformation in reverse, finding the value it would turn into `output`
+pub fn apply_reverse(
+    top_level: &TopLevel,
+    trans: &Sp<Transformation>,
+    output: &Value,
+) -> Result<Value, RuntimeError> {
+    reverse_run_singe_tranform(trans, output, &top_level.functions).map_err(|e| e.at(trans.span))
+}
+
+/// Constructs an expression that does not depend on any variables or functions, like a literal
+pub fn evaluate(expr: &Expression) -> Result<Value, RuntimeError> {
+    expr.construct(&mut Variables::new(), &Functions::new())
 }
 
 fn run_func(
     func: LocalIntern<String>,
-    value: Value,
+    mut value: Value,
     functions: &Functions,
 ) -> Result<Value, RuntimeError> {
     let transforms = functions
         .get(&func)
-        .ok_or_else(|| RuntimeError::ValueError(format!("Missing `{}` function", func)))?;
-    run_tranforms(transforms, value, functions)
+        .ok_or_else(|| RuntimeError::ValueError(format!("Missing `{}` function", func), None))?;
+    for (i, trans) in transforms.data.iter().enumerate() {
+        value = run_single_transform(trans, &value, functions).map_err(|e| {
+            e.at(trans.span)
+                .in_frame(Frame::new(func, i, Direction::Forward, value.clone()))
+        })?
+    }
+    Ok(value)
 }
 
 fn run_tranforms(
-    transforms: &[Transformation],
+    transforms: &[Sp<Transformation>],
     mut value: Value,
-    functions: &HashMap<LocalIntern<String>, Vec<parser::ast::Transformation>>,
+    functions: &Functions,
 ) -> Result<Value, RuntimeError> {
     for trans in transforms {
-        value = run_single_transform(trans, value, functions)?
+        value = run_single_transform(trans, &value, functions)?
     }
     Ok(value)
 }
 
 fn run_single_transform(
-    trans: &Transformation,
-    value: Value,
-    functions: &HashMap<LocalIntern<String>, Vec<Transformation>>,
+    trans: &Sp<Transformation>,
+    value: &Value,
+    functions: &Functions,
 ) -> Result<Value, RuntimeError> {
-    Ok(match trans {
+    Ok(match &trans.data {
         Forced {
             destruct,
             construct,
         } => {
-            let mut env = Variables::new();
-            destruct.destruct(&value, &mut env, functions)?;
-            let out = construct.construct(&mut env, functions)?;
-
-            for (name, value) in env.polyidents.iter() {
-                if !value.is_empty() {
-                    return Err(RuntimeError::ValueError(format!(
-                        "Polyident {} was used more times in the destruct pattern than in the construct pattern",
-                        name
-                    )));
-                }
-            }
-            out
+            let env = destruct_search(destruct, value, functions)
+                .map_err(|e| e.context("pattern defined here".to_string(), Some(destruct.span)))?;
+            construct_with(env, construct, functions, Direction::Forward)?
         }
-        parser::ast::Transformation::Compound(v) => run_tranforms(v, value, functions)?,
+        All {
+            destruct,
+            construct,
+        } => {
+            let (matches, _) = destruct_matches(destruct, value, functions, true)
+                .map_err(|e| e.context("pattern defined here".to_string(), Some(destruct.span)))?;
+            Value::Array(
+                matches
+                    .into_iter()
+                    .map(|env| construct_with(env, construct, functions, Direction::Forward))
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        parser::ast::Transformation::Compound(v) => run_tranforms(v, value.clone(), functions)?,
         parser::ast::Transformation::Try { first, otherwise } => {
-            match run_single_transform(first, value.clone(), functions) {
+            match run_single_transform(first, value, functions) {
                 Ok(v) => v,
-                Err(_) => run_single_transform(otherwise, value, functions)?,
+                Err(first_err) if first_err.is_mismatch() => {
+                    match run_single_transform(otherwise, value, functions) {
+                        Ok(v) => v,
+                        Err(e) if e.is_mismatch() => {
+                            return Err(no_branch_matched(trans, Direction::Forward, first_err, e))
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Err(e) => return Err(e),
             }
         }
     })
 }
 
+/// The variables bound by each way `pattern` matches `value` in the order they are found, or
+/// just the first if `all` is false. Where the pattern could match in several ways the
+/// alternatives are tried in order, and the error from the first attempt that didn't match is
+/// given as well.
+fn destruct_matches(
+    pattern: &Expression,
+    value: &Value,
+    functions: &Functions,
+    all: bool,
+) -> Result<(Vec<Variables>, Option<RuntimeError>), RuntimeError> {
+    let mut matches = Vec::new();
+    let mut first_err = None;
+    let mut choices = Choices::default();
+    loop {
+        let mut env = Variables::new();
+        env.choices = choices;
+        env.quiet = first_err.is_some();
+        match pattern.destruct(value, &mut env, functions) {
+            Ok(_) => {
+                choices = std::mem::take(&mut env.choices);
+                matches.push(env);
+                if !all {
+                    break;
+                }
+            }
+            Err(e) if e.is_mismatch() => {
+                first_err.get_or_insert(e);
+                choices = env.choices;
+            }
+            Err(e) => return Err(e),
+        }
+        if !choices.advance() {
+            break;
+        }
+    }
+    Ok((matches, first_err))
+}
+
+/// The variables bound by the first way `pattern` matches `value`
+fn destruct_search(
+    pattern: &Expression,
+    value: &Value,
+    functions: &Functions,
+) -> Result<Variables, RuntimeError> {
+    let (mut matches, first_err) = destruct_matches(pattern, value, functions, false)?;
+    matches
+        .pop()
+        .ok_or_else(|| first_err.expect("an attempt failed to match"))
+}
+
+/// Constructs `pattern` with the variables a transformation's other pattern bound when running
+/// in `direction`
+fn construct_with(
+    mut env: Variables,
+    pattern: &Expression,
+    functions: &Functions,
+    direction: Direction,
+) -> Result<Value, RuntimeError> {
+    let out = pattern
+        .construct(&mut env, functions)
+        .map_err(RuntimeError::fatal)?;
+    let (destructed, constructed) = match direction {
+        Direction::Forward => ("destruct", "construct"),
+        Direction::Reverse => ("construct", "destruct"),
+    };
+    if let Some(name) = env.leftover_polyident() {
+        return Err(RuntimeError::ValueError(
+            format!(
+                "Polyident {} was used more times in the {} pattern than in the {} pattern",
+                name, destructed, constructed
+            ),
+            None,
+        ));
+    }
+    Ok(out)
+}
+
 fn reverse_run_func(
     func: LocalIntern<String>,
-    output: Value,
+    mut output: Value,
     functions: &Functions,
 ) -> Result<Value, RuntimeError> {
     let transforms = functions
         .get(&func)
-        .ok_or_else(|| RuntimeError::ValueError(format!("Missing `{}` function", func)))?;
-    reverse_run_transforms(transforms, output, functions)
+        .ok_or_else(|| RuntimeError::ValueError(format!("Missing `{}` function", func), None))?;
+    for (i, trans) in transforms.data.iter().enumerate().rev() {
+        output = reverse_run_singe_tranform(trans, &output, functions).map_err(|e| {
+            e.at(trans.span)
+                .in_frame(Frame::new(func, i, Direction::Reverse, output.clone()))
+        })?
+    }
+    Ok(output)
 }
 
 fn reverse_run_transforms(
-    transforms: &[Transformation],
+    transforms: &[Sp<Transformation>],
     mut output: Value,
     functions: &Functions,
 ) -> Result<Value, RuntimeError> {
     for trans in transforms.iter().rev() {
-        output = reverse_run_singe_tranform(trans, output, functions)?
+        output = reverse_run_singe_tranform(trans, &output, functions)?
     }
     Ok(output)
 }
 
 fn reverse_run_singe_tranform(
-    trans: &Transformation,
-    output: Value,
-    functions: &HashMap<LocalIntern<String>, Vec<Transformation>>,
+    trans: &Sp<Transformation>,
+    output: &Value,
+    functions: &Functions,
 ) -> Result<Value, RuntimeError> {
-    Ok(match trans {
+    Ok(match &trans.data {
         Forced {
             destruct,
             construct,
         } => {
-            let mut env = Variables::new();
-            construct.destruct(&output, &mut env, functions)?;
-            let out = destruct.construct(&mut env, functions)?;
-
-            for (name, value) in env.polyidents.iter() {
-                if !value.is_empty() {
-                    return Err(RuntimeError::ValueError(format!(
-                        "Polyident {} was used more times in the construct pattern than in the destruct pattern",
-                        name
-                    )));
+            let env = destruct_search(construct, output, functions)
+                .map_err(|e| e.context("pattern defined here".to_string(), Some(construct.span)))?;
+            construct_with(env, destruct, functions, Direction::Reverse)?
+        }
+        // every match was made from the same input, so any of them gives it back
+        All {
+            destruct,
+            construct,
+        } => {
+            let outputs = match output {
+                Value::Array(a) => a,
+                v => {
+                    return Err(RuntimeError::PatternMismatch(
+                        format!("Expected an array of every match, got {}", v),
+                        Some(trans.span),
+                    ))
+                }
+            };
+            let mut input: Option<Value> = None;
+            for output in outputs {
+                let env = destruct_search(construct, output, functions).map_err(|e| {
+                    e.context("pattern defined here".to_string(), Some(construct.span))
+                })?;
+                let value = construct_with(env, destruct, functions, Direction::Reverse)?;
+                match &input {
+                    Some(input) if input != &value => {
+                        return Err(RuntimeError::PatternMismatch(
+                            format!(
+                                "The matches come from different values, {} and {}",
+                                input, value
+                            ),
+                            Some(trans.span),
+                        ))
+                    }
+                    _ => input = Some(value),
                 }
             }
-            out
+            input.ok_or_else(|| {
+                RuntimeError::ValueError(
+                    "Cannot tell what value has no matches".to_string(),
+                    Some(trans.span),
+                )
+            })?
+        }
+        parser::ast::Transformation::Compound(v) => {
+            reverse_run_transforms(v, output.clone(), functions)?
         }
-        parser::ast::Transformation::Compound(v) => reverse_run_transforms(v, output, functions)?,
         parser::ast::Transformation::Try { first, otherwise } => {
-            match reverse_run_singe_tranform(first, output.clone(), functions) {
+            match reverse_run_singe_tranform(first, output, functions) {
                 Ok(v) => v,
-                Err(_) => reverse_run_singe_tranform(otherwise, output, functions)?,
+                Err(first_err) if first_err.is_mismatch() => {
+                    match reverse_run_singe_tranform(otherwise, output, functions) {
+                        Ok(v) => v,
+                        Err(e) if e.is_mismatch() => {
+                            return Err(no_branch_matched(trans, Direction::Reverse, first_err, e))
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Err(e) => return Err(e),
             }
         }
     })
 }
 
+/// The pattern that is destructed when running `trans` in `direction`
+fn branch_pattern(trans: &Sp<Transformation>, direction: Direction) -> Span {
+    match (&trans.data, direction) {
+        (Forced { destruct, .. } | All { destruct, .. }, Direction::Forward) => destruct.span,
+        (Forced { construct, .. } | All { construct, .. }, Direction::Reverse) => construct.span,
+        _ => trans.span,
+    }
+}
+
+/// Error for the `try_trans` conditional transformation when both of its branches failed. A
+/// `:?` chain is a conditional transformation nested in the `:` branch, so its branches are
+/// listed as well.
+fn no_branch_matched(
+    try_trans: &Sp<Transformation>,
+    direction: Direction,
+    first_err: RuntimeError,
+    otherwise_err: RuntimeError,
+) -> RuntimeError {
+    let (first, otherwise) = match &try_trans.data {
+        Transformation::Try { first, otherwise } => (first, otherwise),
+        _ => unreachable!(),
+    };
+    let mut branches = vec![Branch {
+        pattern: branch_pattern(first, direction),
+        error: first_err,
+    }];
+    match (&otherwise.data, otherwise_err) {
+        (Transformation::Try { .. }, RuntimeError::NoBranchMatched(rest, _)) => {
+            branches.extend(rest)
+        }
+        (_, error) => branches.push(Branch {
+            pattern: branch_pattern(otherwise, direction),
+            error,
+        }),
+    }
+    RuntimeError::NoBranchMatched(branches, Some(try_trans.span))
+}
+
+// errors raised by a node are pointed at that node, so a mismatch deep inside a pattern is
+// reported at the exact sub-pattern that failed rather than the whole transformation
+impl Structure for Expression {
+    fn construct(
+        &self,
+        variables: &mut Variables,
+        functions: &Functions,
+    ) -> Result<Value, RuntimeError> {
+        self.data
+            .construct(variables, functions)
+            .map_err(|e| e.at(self.span))
+    }
+
+    fn destruct_to_value(
+        &self,
+        functions: &Functions,
+        variables: &Variables,
+    ) -> Result<DestructResult, RuntimeError> {
+        self.data
+            .destruct_to_value(functions, variables)
+            .map_err(|e| e.at(self.span))
+    }
+
+    fn destruct(
+        &self,
+        value: &Value,
+        variables: &mut Variables,
+        functions: &Functions,
+    ) -> Result<Option<Value>, RuntimeError> {
+        self.data
+            .destruct(value, variables, functions)
+            .map_err(|e| e.at(self.span))
+    }
+}
+
 impl Structure for Expr {
     fn construct(
         &self,
@@ -331,8 +698,28 @@ impl Structure for Expr {
         functions: &Functions,
     ) -> Result<Value, RuntimeError> {
         match self {
-            Expr::Number(n) => Ok(Value::Number(*n)),
-            Expr::String(s, _) => Ok(Value::String(s.to_owned())), // btw we can make strings localintern
+            Expr::Number(n) => Ok(Value::Number(Number::from_decimal(n))),
+            Expr::Int(n) => Ok(Value::Number(Number::Int(n.clone()))),
+            Expr::String(s) => Ok(Value::String(s.to_owned())), // btw we can make strings localintern
+            Expr::Format(parts) => {
+                let mut out = String::new();
+                for part in parts {
+                    match part {
+                        FormatPart::Text(t) => out.push_str(t),
+                        FormatPart::Interpolation(e) => match e.construct(variables, functions)? {
+                            Value::String(s) => out.push_str(&s),
+                            v => {
+                                return Err(RuntimeError::TypeMismatch(
+                                    "string".to_string(),
+                                    v.to_type().to_string(),
+                                    Some(e.span),
+                                ))
+                            }
+                        },
+                    }
+                }
+                Ok(Value::String(out))
+            }
             Expr::Array(arr) => Ok(Value::Array(
                 arr.iter()
                     .map(|e| -> Result<_, _> { e.construct(variables, functions) })
@@ -343,13 +730,39 @@ impl Structure for Expr {
                     .map(|e| -> Result<_, _> { e.construct(variables, functions) })
                     .collect::<Result<_, _>>()?,
             )),
-            Expr::Ident(i) => variables
-                .get(*i)
-                .cloned()
-                .ok_or_else(|| RuntimeError::ValueError(format!("Identifier {} not found", i))),
+            Expr::Map(entries, rest) => {
+                let mut map = match rest {
+                    Some(rest) => match rest.construct(variables, functions)? {
+                        Value::Map(m) => m,
+                        v => {
+                            return Err(RuntimeError::TypeMismatch(
+                                "map".to_string(),
+                                v.to_type().to_string(),
+                                Some(rest.span),
+                            ))
+                        }
+                    },
+                    None => BTreeMap::new(),
+                };
+                for (k, e) in entries {
+                    if map
+                        .insert(k.clone(), e.construct(variables, functions)?)
+                        .is_some()
+                    {
+                        return Err(RuntimeError::ValueError(
+                            format!("Key {:?} is both given and part of the rest of the map", k),
+                            None,
+                        ));
+                    }
+                }
+                Ok(Value::Map(map))
+            }
+            Expr::Ident(i) => variables.get(*i).cloned().ok_or_else(|| {
+                RuntimeError::ValueError(format!("Identifier {} not found", i), None)
+            }),
 
             Expr::PolyIdent(i) => variables.take_polyident(*i)?.ok_or_else(|| {
-                RuntimeError::ValueError(format!("Poly-identifier {} not found", i))
+                RuntimeError::ValueError(format!("Poly-identifier {} not found", i), None)
             }),
             Expr::Operator(op, a, b) => {
                 use parser::ast::Operator::*;
@@ -401,15 +814,18 @@ impl Structure for Expr {
             Expr::UnaryOp(op, val) => {
                 let val = val.construct(variables, functions)?;
                 match (op, val) {
-                    (UnaryOperator::Neg, Value::Number(n)) => Ok(Value::Number(-n)),
+                    (UnaryOperator::Neg, Value::Number(n)) => Ok(Value::Number(-&n)),
                     (UnaryOperator::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
-                    (a, val) => Err(RuntimeError::ValueError(format!(
-                        "Cannot apply unary operator {:?} to {}",
-                        a, val
-                    ))),
+                    (a, val) => Err(RuntimeError::ValueError(
+                        format!("Cannot apply unary operator {:?} to {}", a, val),
+                        None,
+                    )),
                 }
             }
-            Expr::Any => Err(RuntimeError::ValueError("Cannot construct `_`".to_string())),
+            Expr::Any => Err(RuntimeError::ValueError(
+                "Cannot construct `_`".to_string(),
+                None,
+            )),
             Expr::Call(f, a) => run_func(*f, a.construct(variables, functions)?, functions),
         }
     }
@@ -422,51 +838,51 @@ impl Structure for Expr {
     ) -> Result<Option<Value>, RuntimeError> {
         match &self {
             Expr::Number(n) => {
-                if value == &Value::Number(*n) {
-                    Ok(Some(Value::Number(*n)))
+                let n = Value::Number(Number::from_decimal(n));
+                if value == &n {
+                    Ok(Some(n))
                 } else {
-                    Err(RuntimeError::PatternMismatch(format!(
-                        "Expected number {:?}, got {}",
-                        n, value
-                    )))
+                    Err(variables.mismatch(|| format!("Expected number {}, got {}", n, value)))
+                }
+            }
+            Expr::Int(n) => {
+                let n = Value::Number(Number::Int(n.clone()));
+                if value == &n {
+                    Ok(Some(n))
+                } else {
+                    Err(variables.mismatch(|| format!("Expected number {}, got {}", n, value)))
                 }
             }
             Expr::Bool(b) => {
                 if value == &Value::Bool(*b) {
                     Ok(Some(Value::Bool(*b)))
                 } else {
-                    Err(RuntimeError::PatternMismatch(format!(
-                        "Expected bool {:?}, got {}",
-                        b, value
-                    )))
+                    Err(variables.mismatch(|| format!("Expected bool {:?}, got {}", b, value)))
                 }
             }
-            Expr::String(s, _) => {
+            Expr::String(s) => {
                 if let Value::String(s2) = value {
                     if s == s2 {
                         Ok(Some(Value::String(s.to_owned())))
                     } else {
-                        Err(RuntimeError::PatternMismatch(format!(
-                            "Expected string {:?}, got {:?}",
-                            s, s2
-                        )))
+                        Err(variables.mismatch(|| format!("Expected string {:?}, got {:?}", s, s2)))
                     }
                 } else {
-                    Err(RuntimeError::PatternMismatch(format!(
-                        "Expected string {:?}, got {}",
-                        s, value
-                    )))
+                    Err(variables.mismatch(|| format!("Expected string {:?}, got {}", s, value)))
                 }
             }
+            Expr::Format(parts) => {
+                let parts = destruct_algebra::format_parts(parts, functions, variables)?;
+                destruct_algebra::string_destruct(&parts, value, variables, functions)?;
+                Ok(None)
+            }
             Expr::Array(arr) => {
                 // i fugured out the destruct thing!!
                 match value {
                     Value::Array(arr2) => {
                         if arr.len() != arr2.len() {
-                            return Err(RuntimeError::PatternMismatch(format!(
-                                "Expected array of length {}",
-                                arr.len()
-                            )));
+                            return Err(variables
+                                .mismatch(|| format!("Expected array of length {}", arr.len())));
                         }
                         let mut arr_val = Some(Vec::new());
 
@@ -482,19 +898,15 @@ impl Structure for Expr {
 
                         Ok(arr_val.map(Value::Array))
                     }
-                    a => Err(RuntimeError::PatternMismatch(format!(
-                        "Expected array, got {}",
-                        a
-                    ))),
+                    a => Err(variables.mismatch(|| format!("Expected array, got {}", a))),
                 }
             }
             Expr::Tuple(t) => match value {
                 Value::Tuple(t2) => {
                     if t.len() != t2.len() {
-                        return Err(RuntimeError::PatternMismatch(format!(
-                            "Expected tuple of length {}",
-                            t.len()
-                        )));
+                        return Err(
+                            variables.mismatch(|| format!("Expected tuple of length {}", t.len()))
+                        );
                     }
                     let mut arr_val = Some(Vec::new());
 
@@ -510,10 +922,40 @@ impl Structure for Expr {
 
                     Ok(arr_val.map(Value::Tuple))
                 }
-                a => Err(RuntimeError::PatternMismatch(format!(
-                    "Expected tuple, got {}",
-                    a
-                ))),
+                a => Err(variables.mismatch(|| format!("Expected tuple, got {}", a))),
+            },
+            Expr::Map(entries, rest) => match value {
+                Value::Map(m) => {
+                    let mut remaining = m.clone();
+                    let mut known = true;
+                    for (k, e) in entries {
+                        let v = remaining.remove(k).ok_or_else(|| {
+                            variables.mismatch(|| {
+                                format!("Expected map with key {:?}, got {}", k, value)
+                            })
+                        })?;
+                        known &= e.destruct(&v, variables, functions)?.is_some();
+                    }
+                    match rest {
+                        Some(rest) => {
+                            known &= rest
+                                .destruct(&Value::Map(remaining), variables, functions)?
+                                .is_some()
+                        }
+                        None if !remaining.is_empty() => {
+                            return Err(variables.mismatch(|| {
+                                format!(
+                                    "Unexpected keys {:?} in {}, use `..` to ignore them",
+                                    remaining.keys().collect::<Vec<_>>(),
+                                    value
+                                )
+                            }))
+                        }
+                        None => (),
+                    }
+                    Ok(if known { Some(value.clone()) } else { None })
+                }
+                a => Err(variables.mismatch(|| format!("Expected map, got {}", a))),
             },
             Expr::Ident(i) => {
                 variables.insert(*i, value.clone())?;
@@ -527,10 +969,19 @@ impl Structure for Expr {
             Expr::Operator(op, left, right) => {
                 use parser::ast::Operator::*;
                 use DestructResult::*;
-                match (
+                let sides = (
                     left.destruct_to_value(functions, variables)?,
                     right.destruct_to_value(functions, variables)?,
-                ) {
+                );
+                if let Add = op {
+                    if let Some(parts) =
+                        destruct_algebra::string_sum(&sides, left, right, functions, variables)?
+                    {
+                        destruct_algebra::string_destruct(&parts, value, variables, functions)?;
+                        return Ok(None);
+                    }
+                }
+                match sides {
                     (
                         Known(a) | Partial(PartialValue::Value(a)),
                         Known(b) | Partial(PartialValue::Value(b)),
@@ -556,41 +1007,42 @@ impl Structure for Expr {
                         if &res == value {
                             Ok(Some(res))
                         } else {
-                            Err(RuntimeError::PatternMismatch(format!(
-                                "Expected {} from destruct expression, found {}",
-                                value, res
-                            )))
+                            Err(variables.mismatch(|| {
+                                format!(
+                                    "Expected {} from destruct expression, found {}",
+                                    value, res
+                                )
+                            }))
                         }
                     }
 
                     (Known(left) | Partial(PartialValue::Value(left)), Unknown) => {
                         match op {
                             Add => destruct_algebra::add_left_destruct(
-                                &left, &*right, value, variables, functions,
+                                &left, right, value, variables, functions,
                             )?,
                             Sub => destruct_algebra::sub_left_destruct(
-                                &left, &*right, value, variables, functions,
+                                &left, right, value, variables, functions,
                             )?,
                             Mul => destruct_algebra::mul_left_destruct(
-                                &left, &*right, value, variables, functions,
+                                &left, right, value, variables, functions,
                             )?,
                             Div => destruct_algebra::div_left_destruct(
-                                &left, &*right, value, variables, functions,
+                                &left, right, value, variables, functions,
                             )?,
                             And => destruct_algebra::and_destruct(
-                                &left, &*right, value, variables, functions,
+                                &left, right, value, variables, functions,
                             )?,
                             Or => destruct_algebra::or_destruct(
-                                &left, &*right, value, variables, functions,
+                                &left, right, value, variables, functions,
                             )?,
                             Eq => destruct_algebra::eq_destruct(
-                                &left, &*right, value, variables, functions,
+                                &left, right, value, variables, functions,
                             )?,
                             a => {
-                                return Err(RuntimeError::PatternMismatch(format!(
-                                    "This operator can not be destructed: {:?}",
-                                    a
-                                )))
+                                return Err(variables.mismatch(|| {
+                                    format!("This operator can not be destructed: {:?}", a)
+                                }))
                             }
                         };
                         Ok(None)
@@ -598,150 +1050,101 @@ impl Structure for Expr {
                     (Unknown, Known(right) | Partial(PartialValue::Value(right))) => {
                         match op {
                             Add => destruct_algebra::add_right_destruct(
-                                &right, &*left, value, variables, functions,
+                                &right, left, value, variables, functions,
                             )?,
                             Sub => destruct_algebra::sub_right_destruct(
-                                &right, &*left, value, variables, functions,
+                                &right, left, value, variables, functions,
                             )?,
                             Mul => destruct_algebra::mul_right_destruct(
-                                &right, &*left, value, variables, functions,
+                                &right, left, value, variables, functions,
                             )?,
                             Div => destruct_algebra::div_right_destruct(
-                                &right, &*left, value, variables, functions,
+                                &right, left, value, variables, functions,
                             )?,
                             And => destruct_algebra::and_destruct(
-                                &right, &*left, value, variables, functions,
+                                &right, left, value, variables, functions,
                             )?,
                             Or => destruct_algebra::or_destruct(
-                                &right, &*left, value, variables, functions,
+                                &right, left, value, variables, functions,
                             )?,
                             Eq => destruct_algebra::eq_destruct(
-                                &right, &*left, value, variables, functions,
+                                &right, left, value, variables, functions,
                             )?,
                             a => {
-                                return Err(RuntimeError::PatternMismatch(format!(
-                                    "This operator can not be destructed: {:?}",
-                                    a
-                                )))
+                                return Err(variables.mismatch(|| {
+                                    format!("This operator can not be destructed: {:?}", a)
+                                }))
                             }
                         };
                         Ok(None)
                     }
+                    (Partial(PartialValue::String(parts)), times) if matches!(op, Mul) => {
+                        destruct_algebra::string_repeat_destruct(
+                            &parts, &times, right, value, variables, functions,
+                        )?;
+                        Ok(None)
+                    }
                     (Partial(partial_left), _) => match (partial_left, op) {
                         (
                             PartialValue::Array {
                                 len: Some(len_a), ..
                             },
                             Add,
-                        ) => match value {
-                            Value::Array(arr) => {
-                                let target_val1 = Value::Array(arr[..len_a].to_vec());
-                                let target_val2 = Value::Array(arr[len_a..].to_vec());
+                        ) => match destruct_algebra::sequence(value) {
+                            Some((items, kind)) if items.len() >= len_a => {
+                                let target_val1 = kind(items[..len_a].to_vec());
+                                let target_val2 = kind(items[len_a..].to_vec());
                                 left.destruct(&target_val1, variables, functions)?;
                                 right.destruct(&target_val2, variables, functions)?;
                                 Ok(None)
                             }
-                            a => {
-                                return Err(RuntimeError::PatternMismatch(format!(
-                                    "Cannot add two arrays to get {}",
-                                    a
-                                )))
+                            None if matches!(value, Value::String(_)) => {
+                                destruct_algebra::chars_destruct(
+                                    (left, Some(len_a)),
+                                    (right, None),
+                                    value,
+                                    variables,
+                                    functions,
+                                )?;
+                                Ok(None)
                             }
+                            _ => Err(variables.mismatch(|| {
+                                format!(
+                                    "Cannot add {} elements to something to get {}",
+                                    len_a, value
+                                )
+                            })),
                         },
                         (
                             PartialValue::Array {
                                 len: Some(len_a), ..
                             },
                             Mul,
-                        ) => match value {
-                            Value::Array(arr) => {
-                                if arr.len() % len_a != 0 {
-                                    return Err(RuntimeError::PatternMismatch(format!(
-                                            "Cannot multiply array of length {} by anything to get an array of length {}",
-                                            len_a, arr.len(),
+                        ) => match destruct_algebra::sequence(value) {
+                            Some((items, _)) => {
+                                if items.len() % len_a != 0 {
+                                    return Err(variables.mismatch(|| format!(
+                                            "Cannot multiply {} elements by anything to get {} elements",
+                                            len_a, items.len(),
                                         )));
                                 }
-                                let num = Value::Number((arr.len() / len_a) as f64);
+                                let num = Value::Number((items.len() / len_a).into());
                                 right.destruct(&num, variables, functions)?;
-                                destruct_algebra::mul_right_destruct(
-                                    &num, &*left, value, variables, functions,
-                                )?;
+                                // nothing is repeated zero times, so there is nothing left to match
+                                if !items.is_empty() {
+                                    destruct_algebra::mul_right_destruct(
+                                        &num, left, value, variables, functions,
+                                    )?;
+                                }
                                 Ok(None)
                             }
-                            a => {
-                                return Err(RuntimeError::PatternMismatch(format!(
-                                    "Cannot multiply array to get {}",
-                                    a
-                                )))
-                            }
+                            None => Err(variables.mismatch(|| {
+                                format!("Cannot multiply an array or tuple to get {}", value)
+                            })),
                         },
-                        // (PartialValue::String(a), Add) => match value {
-                        //     Value::String(v) => {
-                        //         unimplemented!()
-                        //     }
-                        //     a => {
-                        //         return Err(RuntimeError::PatternMismatch(format!(
-                        //             "Cannot add two strings to get {}",
-                        //             a
-                        //         )))
-                        //     }
-                        // },
-
-                        // (PartialValue::String(a), Mul) => match value {
-                        //     Value::String(v) => {
-                        //         let mut counter = 0;
-                        //         let mut str = v.clone();
-                        //         let mut iter = a.into_iter();
-                        //         for part in iter {
-                        //             match part {
-                        //                 PartialStringPart::String(s) => {
-                        //                     if !str.starts_with(s) {
-                        //                         return Err(RuntimeError::PatternMismatch("String does not contain the expected substring".to_string()));
-                        //                     }
-                        //                     str = str[s.len()..].to_string();
-                        //                 }
-                        //                 PartialStringPart::Expr(mut e) => {
-                        //                     let mut next = iter.next();
-                        //                     if next.is_none() {
-                        //                         e.destruct(
-                        //                             &Value::String(str),
-                        //                             variables,
-                        //                             functions,
-                        //                         );
-                        //                     } else {
-                        //                         while let Some(PartialStringPart::Expr(e2)) = next {
-                        //                             e = Expr::Operator(
-                        //                                 parser::ast::Operator::Add,
-                        //                                 Box::new(e),
-                        //                                 Box::new(e2),
-                        //                             );
-                        //                             next = iter.next();
-                        //                         }
-                        //                         if let Some(PartialStringPart::String(s)) = next {
-                        //                             e.destruct(
-                        //                                 &Value::String(str),
-                        //                                 variables,
-                        //                                 functions,
-                        //                             );
-                        //                             str = str[s.len()..].to_string();
-                        //                         } else {
-                        //                             unreachable!();
-                        //                         }
-                        //                     }
-                        //                 }
-                        //             }
-                        //         }
-                        //         todo!()
-                        //     }
-                        //     a => {
-                        //         return Err(RuntimeError::PatternMismatch(format!(
-                        //             "Cannot multiply string to get {}",
-                        //             a
-                        //         )))
-                        //     }
-                        // },
                         _ => Err(RuntimeError::ValueError(
                             "Cannot destruct expression with two unknowns".to_string(),
+                            None,
                         )),
                     },
 
@@ -751,30 +1154,55 @@ impl Structure for Expr {
                                 len: Some(len_b), ..
                             },
                             Add,
-                        ) => match value {
-                            Value::Array(arr) => {
-                                let len = arr.len() - len_b;
-                                let target_val1 = Value::Array(arr[..len].to_vec());
-                                let target_val2 = Value::Array(arr[len..].to_vec());
+                        ) => match destruct_algebra::sequence(value) {
+                            Some((items, kind)) if items.len() >= len_b => {
+                                let len = items.len() - len_b;
+                                let target_val1 = kind(items[..len].to_vec());
+                                let target_val2 = kind(items[len..].to_vec());
                                 left.destruct(&target_val1, variables, functions)?;
                                 right.destruct(&target_val2, variables, functions)?;
                                 Ok(None)
                             }
-                            a => {
-                                return Err(RuntimeError::PatternMismatch(format!(
-                                    "Cannot add two arrays to get {}",
-                                    a
-                                )))
+                            None if matches!(value, Value::String(_)) => {
+                                destruct_algebra::chars_destruct(
+                                    (left, None),
+                                    (right, Some(len_b)),
+                                    value,
+                                    variables,
+                                    functions,
+                                )?;
+                                Ok(None)
                             }
+                            _ => Err(variables.mismatch(|| {
+                                format!(
+                                    "Cannot add something to {} elements to get {}",
+                                    len_b, value
+                                )
+                            })),
                         },
 
                         _ => Err(RuntimeError::ValueError(
                             "Cannot destruct expression with two unknowns".to_string(),
+                            None,
                         )),
                     },
 
+                    (Unknown, Unknown) if matches!(op, Add) => {
+                        destruct_algebra::add_split_destruct(
+                            left, right, value, variables, functions,
+                        )?;
+                        Ok(None)
+                    }
+                    (Unknown, Unknown) if matches!(op, Mul) => {
+                        destruct_algebra::mul_split_destruct(
+                            left, right, value, variables, functions,
+                        )?;
+                        Ok(None)
+                    }
+
                     _ => Err(RuntimeError::ValueError(
                         "Cannot destruct expression with two unknowns".to_string(),
+                        None,
                     )),
                 }
             }
@@ -786,10 +1214,8 @@ impl Structure for Expr {
                     // !x = b
                     (UnaryOperator::Not, Value::Bool(b)) => Value::Bool(!b),
                     (op, v) => {
-                        return Err(RuntimeError::ValueError(format!(
-                            "Cannot apply unary operator {:?} to {}",
-                            op, v
-                        )))
+                        return Err(variables
+                            .mismatch(|| format!("Cannot apply unary operator {:?} to {}", op, v)))
                     }
                 };
                 val.destruct(&target_value, variables, functions)
@@ -812,15 +1238,23 @@ impl Structure for Expr {
     ) -> Result<DestructResult, RuntimeError> {
         use DestructResult::*;
         match &self {
-            Expr::Number(n) => Ok(Known(Value::Number(*n))),
+            Expr::Number(n) => Ok(Known(Value::Number(Number::from_decimal(n)))),
+            Expr::Int(n) => Ok(Known(Value::Number(Number::Int(n.clone())))),
             Expr::Bool(b) => Ok(Known(Value::Bool(*b))),
-            Expr::String(s, _) => Ok(Known(Value::String(s.clone()))),
+            Expr::String(s) => Ok(Known(Value::String(s.clone()))),
+            Expr::Format(parts) => {
+                match &destruct_algebra::format_parts(parts, functions, variables)?[..] {
+                    [] => Ok(Known(Value::String(String::new()))),
+                    [PartialStringPart::String(s)] => Ok(Known(Value::String(s.clone()))),
+                    parts => Ok(Partial(PartialValue::String(parts.to_vec()))),
+                }
+            }
             Expr::Array(arr) => {
                 let mut arr_val = Vec::new();
                 let mut known = true;
                 for e in arr {
                     let val = e.destruct_to_value(functions, variables)?;
-                    if let Unknown = val {
+                    if !matches!(val, Known(_)) {
                         known = false;
                     }
                     arr_val.push(val);
@@ -832,6 +1266,7 @@ impl Structure for Expr {
                 } else {
                     Ok(Partial(PartialValue::Array {
                         len: Some(arr_val.len()),
+                        tuple: false,
                         known_elems: arr_val
                             .into_iter()
                             .enumerate()
@@ -849,20 +1284,49 @@ impl Structure for Expr {
                 let mut known = true;
                 for e in t {
                     let val = e.destruct_to_value(functions, variables)?;
-                    if let Unknown = val {
+                    if !matches!(val, Known(_)) {
                         known = false;
-                        break;
                     }
                     arr_val.push(val);
                 }
                 if known {
-                    Ok(Known(Value::Array(
+                    Ok(Known(Value::Tuple(
                         arr_val.into_iter().map(|v| v.unwrap()).collect(),
                     )))
                 } else {
-                    Ok(Unknown)
+                    Ok(Partial(PartialValue::Array {
+                        len: Some(arr_val.len()),
+                        tuple: true,
+                        known_elems: arr_val
+                            .into_iter()
+                            .enumerate()
+                            .filter_map(|(a, b)| match b {
+                                Known(v) => Some((a, PartialValue::Value(v))),
+                                Partial(v) => Some((a, v)),
+                                _ => None,
+                            })
+                            .collect(),
+                    }))
                 }
             }
+            Expr::Map(entries, rest) => {
+                let mut map = match rest {
+                    Some(rest) => match rest.destruct_to_value(functions, variables)? {
+                        Known(Value::Map(m)) => m,
+                        _ => return Ok(Unknown),
+                    },
+                    None => BTreeMap::new(),
+                };
+                for (k, e) in entries {
+                    match e.destruct_to_value(functions, variables)? {
+                        Known(v) => {
+                            map.insert(k.clone(), v);
+                        }
+                        _ => return Ok(Unknown),
+                    }
+                }
+                Ok(Known(Value::Map(map)))
+            }
             Expr::Ident(i) => {
                 if let Some(v) = variables.get(*i) {
                     Ok(Known(v.clone()))
@@ -880,10 +1344,19 @@ impl Structure for Expr {
 
             Expr::Operator(op, left, right) => {
                 use parser::ast::Operator::*;
-                match (
+                let sides = (
                     left.destruct_to_value(functions, variables)?,
                     right.destruct_to_value(functions, variables)?,
-                ) {
+                );
+                if let Add = op {
+                    // text with unknowns around it can still be matched against a string
+                    if let Some(parts) =
+                        destruct_algebra::string_sum(&sides, left, right, functions, variables)?
+                    {
+                        return Ok(Partial(PartialValue::String(parts)));
+                    }
+                }
+                match sides {
                     (
                         Known(a) | Partial(PartialValue::Value(a)),
                         Known(b) | Partial(PartialValue::Value(b)),
@@ -893,22 +1366,44 @@ impl Structure for Expr {
                             Add => a.add(&b)?,
                             Sub => a.sub(&b)?,
                             Mul => match (a, b) {
-                                (Value::Number(a), Value::Number(b)) => Value::Number(a * b),
+                                (Value::Number(a), Value::Number(b)) => Value::Number(&a * &b),
+                                (
+                                    Value::String(_) | Value::Array(_) | Value::Tuple(_),
+                                    Value::Number(b),
+                                ) if b.to_count().is_none() =>
+                                {
+                                    return Err(RuntimeError::ValueError(
+                                        format!(
+                                            "Can only repeat a value a whole number of times, not {}",
+                                            b
+                                        ),
+                                        None,
+                                    ))
+                                }
                                 (Value::String(a), Value::Number(b)) => {
-                                    Value::String(a.repeat(b as usize))
-                                } // ensure b is int?
+                                    let n = b.to_count().unwrap();
+                                    repeated_len(a.len(), n)?;
+                                    Value::String(a.repeat(n))
+                                }
                                 (Value::Array(a), Value::Number(b)) => Value::Array(
                                     a.iter()
                                         .cloned()
                                         .cycle()
-                                        .take(b as usize * a.len())
+                                        .take(repeated_len(a.len(), b.to_count().unwrap())?)
+                                        .collect(),
+                                ),
+                                (Value::Tuple(a), Value::Number(b)) => Value::Tuple(
+                                    a.iter()
+                                        .cloned()
+                                        .cycle()
+                                        .take(repeated_len(a.len(), b.to_count().unwrap())?)
                                         .collect(),
                                 ),
                                 (a, b) => {
-                                    return Err(RuntimeError::ValueError(format!(
-                                        "Cannot multiply {} and {}",
-                                        a, b
-                                    )))
+                                    return Err(RuntimeError::ValueError(
+                                        format!("Cannot multiply {} and {}", a, b),
+                                        None,
+                                    ))
                                 }
                             },
                             Div => a.div(&b)?,
@@ -924,62 +1419,16 @@ impl Structure for Expr {
 
                         Ok(Known(res))
                     }
+                    // (a + ",") * 3 is still text with unknowns around it
+                    (Partial(PartialValue::String(parts)), Known(Value::Number(n)))
+                        if matches!(op, Mul) && n.to_count().is_some() =>
+                    {
+                        match &destruct_algebra::repeat_parts(&parts, n.to_count().unwrap())[..] {
+                            [] => Ok(Known(Value::String(String::new()))),
+                            parts => Ok(Partial(PartialValue::String(parts.to_vec()))),
+                        }
+                    }
 
-                    // (Partial(PartialValue::String(a)), Partial(PartialValue::String(b))) => {
-                    //     if let Add = op {
-                    //         let mut out = a;
-                    //         out.extend(b);
-                    //         Ok(Partial(PartialValue::String(out)))
-                    //     } else {
-                    //         Err(RuntimeError::ValueError(format!(
-                    //             "Cannot apply operator {:?} to two strings",
-                    //             op
-                    //         )))
-                    //     }
-                    // }
-
-                    // (
-                    //     Partial(PartialValue::String(a)),
-                    //     Known(b) | Partial(PartialValue::Value(b)),
-                    // ) => match (op, b) {
-                    //     (Mul, Value::Number(n)) => {
-                    //         if n.fract() != 0.0 {
-                    //             return Err(RuntimeError::ValueError(format!(
-                    //                 "Cannot multiply string by non-integer {}",
-                    //                 n
-                    //             )));
-                    //         }
-                    //         let mut out = Vec::new();
-                    //         for _ in 0..(n as usize) {
-                    //             out.extend(a.clone());
-                    //         }
-                    //         Ok(Partial(PartialValue::String(out)))
-                    //     }
-                    //     (Add, Value::String(s)) => {
-                    //         let mut out = a;
-                    //         out.push(PartialStringPart::String(s));
-                    //         Ok(Partial(PartialValue::String(out)))
-                    //     }
-                    //     (op, b) => Err(RuntimeError::ValueError(format!(
-                    //         "Cannot apply operator {:?} to string and {}",
-                    //         op, b
-                    //     ))),
-                    // },
-
-                    // (
-                    //     Known(a) | Partial(PartialValue::Value(a)),
-                    //     Partial(PartialValue::String(b)),
-                    // ) => match (op, a) {
-                    //     (Add, Value::String(s)) => {
-                    //         let mut out = vec![PartialStringPart::String(s)];
-                    //         out.extend(b);
-                    //         Ok(Partial(PartialValue::String(out)))
-                    //     }
-                    //     (op, a) => Err(RuntimeError::ValueError(format!(
-                    //         "Cannot apply operator {:?} to string and {}",
-                    //         op, a
-                    //     ))),
-                    // },
                     _ => Ok(Unknown),
                 }
             }
@@ -990,21 +1439,135 @@ impl Structure for Expr {
             Expr::UnaryOp(op, val) => {
                 match (op, val.destruct_to_value(functions, variables)?) {
                     // -x = n
-                    (UnaryOperator::Neg, Known(Value::Number(n))) => Ok(Known(Value::Number(-n))),
+                    (UnaryOperator::Neg, Known(Value::Number(n))) => Ok(Known(Value::Number(-&n))),
                     // !x = b
                     (UnaryOperator::Not, Known(Value::Bool(b))) => Ok(Known(Value::Bool(!b))),
-                    (op, Known(v)) => Err(RuntimeError::ValueError(format!(
-                        "Cannot apply unary operator {:?} to {}",
-                        op, v
-                    ))),
+                    (op, Known(v)) => Err(RuntimeError::ValueError(
+                        format!("Cannot apply unary operator {:?} to {}", op, v),
+                        None,
+                    )),
                     _ => Ok(Unknown),
                 }
             }
             Expr::Any => Ok(Unknown),
             Expr::Call(f, a) => match a.destruct_to_value(functions, variables)? {
-                Known(v) => Ok(Known(run_func(*f, v, functions)?)), // run function normally because the value is known
+                // run function normally because the value is known
+                Known(v) => Ok(Known(run_func(*f, v, functions)?)),
                 _ => Ok(Unknown),
             }, // ??
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parser::Lexer;
+
+    fn value(literal: &str) -> Value {
+        evaluate(&Lexer::new(literal, None).parse_expression().unwrap()).unwrap()
+    }
+
+    fn run(source: &str, input: &str) -> Result<Value, RuntimeError> {
+        let top_level = Lexer::new(source, None).parse().unwrap();
+        call(
+            &top_level,
+            LocalIntern::new("main".to_string()),
+            value(input),
+        )
+    }
+
+    fn run_reverse(source: &str, output: &str) -> Result<Value, RuntimeError> {
+        let top_level = Lexer::new(source, None).parse().unwrap();
+        call_reverse(
+            &top_level,
+            LocalIntern::new("main".to_string()),
+            value(output),
+        )
+    }
+
+    #[test]
+    fn splits_are_tried_shortest_first() {
+        let split = "main := a + b -> (a, b);";
+        assert_eq!(run(split, "[1, 2]").unwrap(), value("([], [1, 2])"));
+        assert_eq!(run(split, r#""ab""#).unwrap(), value(r#"("", "ab")"#));
+        let repeated = "main := x * n -> (x, n);";
+        assert_eq!(run(repeated, r#""abab""#).unwrap(), value(r#"("ab", 2)"#));
+        assert_eq!(run(repeated, "[1, 1]").unwrap(), value("([1], 2)"));
+    }
+
+    #[test]
+    fn backtracks_until_the_whole_pattern_matches() {
+        let source = r#"main := (a + " " + b, b) -> a;"#;
+        assert_eq!(
+            run(source, r#"("hello big world", "big world")"#).unwrap(),
+            value(r#""hello""#)
+        );
+        let source = "main := a + [0] + b -> (a, b);";
+        assert_eq!(run(source, "[1, 0, 2, 0]").unwrap(), value("([1], [2, 0])"));
+        assert!(run(source, "[1, 2]").unwrap_err().is_mismatch());
+    }
+
+    #[test]
+    fn every_match_is_in_search_order() {
+        assert_eq!(
+            run("main := a + b => (a, b);", r#""ab""#).unwrap(),
+            value(r#"[("", "ab"), ("a", "b"), ("ab", "")]"#)
+        );
+        assert_eq!(
+            run("main := [1, a] + b => a;", "[2, 3]").unwrap(),
+            value("[]")
+        );
+    }
+
+    #[test]
+    fn format_strings_construct_and_destruct() {
+        let source = r#"main := f"{name} is {age::#number~>#string}" -> (name, age);"#;
+        assert_eq!(
+            run(source, r#""Ann is 30""#).unwrap(),
+            value(r#"("Ann", 30)"#)
+        );
+        assert_eq!(
+            run_reverse(source, r#"("Ann", 30)"#).unwrap(),
+            value(r#""Ann is 30""#)
+        );
+        assert_eq!(
+            run(r#"main := f"{a}.{b}" -> (a, b);"#, r#""1.2.3""#).unwrap(),
+            value(r#"("1", "2.3")"#)
+        );
+        assert_eq!(
+            run(r#"main := a -> f"{{{a}}}";"#, r#""x""#).unwrap(),
+            value(r#""{x}""#)
+        );
+    }
+
+    #[test]
+    fn array_patterns_match_characters() {
+        let source = "main := [c] + rest -> (c, rest);";
+        assert_eq!(
+            run(source, r#""héllo""#).unwrap(),
+            value(r#"("h", "éllo")"#)
+        );
+        assert_eq!(
+            run("main := rest + [c] -> (c, rest);", r#""hello""#).unwrap(),
+            value(r#"("o", "hell")"#)
+        );
+        assert!(run(source, r#""""#).unwrap_err().is_mismatch());
+    }
+
+    #[test]
+    fn string_repetition_splits_by_characters() {
+        let source = "main := a * 2 -> a;";
+        assert_eq!(run(source, r#""éé""#).unwrap(), value(r#""é""#));
+        assert!(run(source, r#""aéa""#).unwrap_err().is_mismatch());
+        let join = r#"main := "a,b" / d -> d;"#;
+        assert_eq!(run(join, r#"["a", "b"]"#).unwrap(), value(r#"",""#));
+        assert!(run(join, r#"["a"]"#).unwrap_err().is_mismatch());
+    }
+
+    #[test]
+    fn repeating_too_many_times_is_an_error() {
+        let source = "main := [1, 2] * 9223372036854775808 + a -> a;";
+        assert!(!run(source, "[1]").unwrap_err().is_mismatch());
+    }
+}
@@ -0,0 +1,489 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+};
+
+use parser::{
+    ast::{
+        Expr, Expression, FormatPart, Function, Operator, TopLevel, Transformation, Type,
+        UnaryOperator,
+    },
+    internment::LocalIntern,
+    parser::{Sp, Span},
+};
+
+/// How well a function can be run in reverse, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Invertibility {
+    /// Running it in reverse on anything it outputs gives back the input it came from
+    Reversible,
+    /// Running it in reverse only works on some of its outputs, or may give back a different
+    /// input than the one the output came from
+    Partial,
+    /// Running it in reverse never works
+    ForwardOnly,
+}
+
+impl Display for Invertibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Invertibility::Reversible => write!(f, "reversible"),
+            Invertibility::Partial => write!(f, "partially reversible"),
+            Invertibility::ForwardOnly => write!(f, "forward-only"),
+        }
+    }
+}
+
+/// Something in a function that keeps it from being reversible
+#[derive(Debug, Clone)]
+pub struct Reason {
+    /// How much of the transformation it is in can still be reversed
+    pub invertibility: Invertibility,
+    pub message: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionAnalysis {
+    pub name: LocalIntern<String>,
+    pub invertibility: Invertibility,
+    /// Why the function isn't reversible, empty if it is
+    pub reasons: Vec<Reason>,
+}
+
+/// Analyses every function, in the order they appear in the source
+pub fn analyze(top_level: &TopLevel) -> Vec<FunctionAnalysis> {
+    let mut names = top_level.functions.iter().collect::<Vec<_>>();
+    names.sort_by_key(|(_, f)| f.span.start);
+    let mut analyzer = Analyzer::new(top_level);
+    names
+        .into_iter()
+        .filter_map(|(name, _)| analyzer.function(*name))
+        .collect()
+}
+
+/// Analyses the function `name`, `None` if there is no such function
+pub fn analyze_function(
+    top_level: &TopLevel,
+    name: LocalIntern<String>,
+) -> Option<FunctionAnalysis> {
+    Analyzer::new(top_level).function(name)
+}
+
+/// Running a transformation in reverse destructs its constructing pattern and constructs its
+/// destructuring pattern, so this follows what [`crate::traits::Structure::destruct`] can
+/// undo. Other than a branch whose constructing pattern matches anything while its destructing
+/// pattern doesn't, it assumes the branches of a conditional transformation construct values
+/// that can be told apart.
+struct Analyzer<'a> {
+    functions: &'a HashMap<LocalIntern<String>, Function>,
+    done: HashMap<LocalIntern<String>, Invertibility>,
+    /// Functions being analysed, which recursive calls assume to be reversible
+    in_progress: HashSet<LocalIntern<String>>,
+}
+
+/// An identifier, and whether it is a polyvalue
+type Name = (LocalIntern<String>, bool);
+
+impl<'a> Analyzer<'a> {
+    fn new(top_level: &'a TopLevel) -> Self {
+        Self {
+            functions: &top_level.functions,
+            done: HashMap::new(),
+            in_progress: HashSet::new(),
+        }
+    }
+
+    fn function(&mut self, name: LocalIntern<String>) -> Option<FunctionAnalysis> {
+        let transforms = self.functions.get(&name)?;
+        self.in_progress.insert(name);
+        let mut reasons = Vec::new();
+        let invertibility = transforms
+            .data
+            .iter()
+            .map(|t| self.transformation(t, &mut reasons))
+            .max()
+            .unwrap_or(Invertibility::Reversible);
+        self.in_progress.remove(&name);
+        self.done.insert(name, invertibility);
+        Some(FunctionAnalysis {
+            name,
+            invertibility,
+            reasons,
+        })
+    }
+
+    /// How far a call to `name` can be reversed, `None` if there is no such function
+    fn callee(&mut self, name: LocalIntern<String>) -> Option<Invertibility> {
+        if self.in_progress.contains(&name) {
+            return Some(Invertibility::Reversible);
+        }
+        match self.done.get(&name) {
+            Some(i) => Some(*i),
+            None => self.function(name).map(|f| f.invertibility),
+        }
+    }
+
+    fn transformation(
+        &mut self,
+        trans: &Sp<Transformation>,
+        reasons: &mut Vec<Reason>,
+    ) -> Invertibility {
+        match &trans.data {
+            Transformation::Forced {
+                destruct,
+                construct,
+            } => self.patterns(destruct, construct, reasons),
+            Transformation::All {
+                destruct,
+                construct,
+            } => {
+                reasons.push(Reason {
+                    invertibility: Invertibility::Partial,
+                    message: "an empty array of matches doesn't tell what value had no matches"
+                        .to_string(),
+                    span: trans.span,
+                });
+                self.patterns(destruct, construct, reasons)
+                    .max(Invertibility::Partial)
+            }
+            Transformation::Compound(transforms) => transforms
+                .iter()
+                .map(|t| self.transformation(t, reasons))
+                .max()
+                .unwrap_or(Invertibility::Reversible),
+            // running in reverse tries the branches in order, so values from a branch that can
+            // be reversed still can. If the first branch also matches anything when running
+            // forward, the other branches never run, so taking it in reverse loses nothing.
+            Transformation::Try { first, otherwise } => {
+                let catch_all = |e: &Expression| matches!(e.data, Expr::Ident(_) | Expr::Any);
+                let shadows = match &first.data {
+                    Transformation::Forced {
+                        destruct,
+                        construct,
+                    } if catch_all(construct) && !catch_all(destruct) => {
+                        reasons.push(Reason {
+                            invertibility: Invertibility::Partial,
+                            message: "running in reverse always takes this branch, as its constructing pattern matches anything".to_string(),
+                            span: construct.span,
+                        });
+                        Invertibility::Partial
+                    }
+                    _ => Invertibility::Reversible,
+                };
+                match (
+                    self.transformation(first, reasons),
+                    self.transformation(otherwise, reasons),
+                ) {
+                    (Invertibility::ForwardOnly, Invertibility::ForwardOnly) => {
+                        Invertibility::ForwardOnly
+                    }
+                    (a, b) => a.max(b).min(Invertibility::Partial).max(shadows),
+                }
+            }
+        }
+    }
+
+    fn patterns(
+        &mut self,
+        destruct: &Expression,
+        construct: &Expression,
+        reasons: &mut Vec<Reason>,
+    ) -> Invertibility {
+        let mut known = HashSet::new();
+        let undone = self.destruct(construct, &mut known, reasons);
+        undone.max(rebuild(
+            destruct,
+            self.functions,
+            &known,
+            &mut HashSet::new(),
+            reasons,
+        ))
+    }
+
+    /// How well destructing `expr` finds its unknowns, adding them to `known`
+    fn destruct(
+        &mut self,
+        expr: &Expression,
+        known: &mut HashSet<Name>,
+        reasons: &mut Vec<Reason>,
+    ) -> Invertibility {
+        let mut reason = |invertibility, message: String| {
+            reasons.push(Reason {
+                invertibility,
+                message,
+                span: expr.span,
+            });
+            invertibility
+        };
+        let level = match &expr.data {
+            Expr::Ident(name) => {
+                known.insert((*name, false));
+                return Invertibility::Reversible;
+            }
+            Expr::PolyIdent(name) => {
+                known.insert((*name, true));
+                return Invertibility::Reversible;
+            }
+            Expr::Format(parts) => {
+                let free = parts
+                    .iter()
+                    .filter(|p| matches!(p, FormatPart::Interpolation(e) if is_free(e, known)))
+                    .count();
+                if free > 1 {
+                    reason(
+                        Invertibility::Partial,
+                        "each unknown of the format string takes the text up to the first place the text after it appears, which could have been part of it".to_string(),
+                    )
+                } else {
+                    Invertibility::Reversible
+                }
+            }
+            Expr::Call(f, arg) if !is_known(arg, known) => match self.callee(*f) {
+                Some(Invertibility::Reversible) => Invertibility::Reversible,
+                Some(level) => reason(level, format!("calls `{}`", f)),
+                None => reason(
+                    Invertibility::ForwardOnly,
+                    format!("calls `{}`, which is not defined", f),
+                ),
+            },
+            Expr::Operator(op, left, right) => {
+                let (l, r) = (is_known(left, known), is_known(right, known));
+                match op {
+                    _ if l && r => Invertibility::Reversible,
+                    Operator::Neq | Operator::Lt | Operator::Gt | Operator::Le | Operator::Ge => {
+                        reason(
+                            Invertibility::ForwardOnly,
+                            format!(
+                                "`{}` only gives whether it holds, not what it compared",
+                                symbol(op)
+                            ),
+                        )
+                    }
+                    Operator::And | Operator::Or | Operator::Eq if l || r => reason(
+                        Invertibility::Partial,
+                        format!(
+                            "`{}` can't tell the unknown side when the known side decides the result",
+                            symbol(op)
+                        ),
+                    ),
+                    Operator::Add => {
+                        // `a + "," + b` is matched as a whole
+                        let mut parts = Vec::new();
+                        sum_parts(expr, &mut parts);
+                        let level = self.sum(&parts, known, &mut reason);
+                        return parts
+                            .into_iter()
+                            .map(|p| self.destruct(p, known, reasons))
+                            .fold(level, Invertibility::max);
+                    }
+                    _ if l || r => Invertibility::Reversible,
+                    Operator::Mul => self.repeat(left, known, &mut reason),
+                    _ => reason(
+                        Invertibility::ForwardOnly,
+                        format!("`{}` of two unknowns can't be undone", symbol(op)),
+                    ),
+                }
+            }
+            _ => Invertibility::Reversible,
+        };
+        children(expr)
+            .into_iter()
+            .map(|e| self.destruct(e, known, reasons))
+            .fold(level, Invertibility::max)
+    }
+
+    /// How well `a + b + ...` is split into its parts
+    fn sum(
+        &self,
+        parts: &[&Expression],
+        known: &HashSet<Name>,
+        reason: &mut impl FnMut(Invertibility, String) -> Invertibility,
+    ) -> Invertibility {
+        if parts.iter().filter(|p| is_free(p, known)).count() <= 1 {
+            return Invertibility::Reversible;
+        }
+        match kind(parts) {
+            Some(Kind::Number) => reason(
+                Invertibility::ForwardOnly,
+                "adding unknown numbers loses what each of them was".to_string(),
+            ),
+            Some(Kind::Sequence) => reason(
+                Invertibility::Partial,
+                "`+` with several unknowns uses the first split that fits, which may not be the one it was made from".to_string(),
+            ),
+            // they could be numbers, which is the worse case
+            None => reason(
+                Invertibility::ForwardOnly,
+                "`+` of unknowns can't be undone on numbers, and on strings and arrays it uses the first split that fits".to_string(),
+            ),
+        }
+    }
+
+    /// How well `x * n` is undone when neither side is known
+    fn repeat(
+        &self,
+        repeated: &Expression,
+        known: &HashSet<Name>,
+        reason: &mut impl FnMut(Invertibility, String) -> Invertibility,
+    ) -> Invertibility {
+        let mut parts = Vec::new();
+        sum_parts(repeated, &mut parts);
+        if parts.iter().all(|p| !is_free(p, known)) {
+            // every repetition has the same length, like `[*a] * n`
+            return Invertibility::Reversible;
+        }
+        match kind(&parts) {
+            Some(Kind::Number) => reason(
+                Invertibility::ForwardOnly,
+                "multiplying unknown numbers loses what each of them was".to_string(),
+            ),
+            Some(Kind::Sequence) => reason(
+                Invertibility::Partial,
+                "the repetitions are split at their text, which could also be inside the unknowns".to_string(),
+            ),
+            None => reason(
+                Invertibility::ForwardOnly,
+                "`*` of two unknowns can't be undone on numbers, and on strings and arrays it uses the shortest piece that repeats".to_string(),
+            ),
+        }
+    }
+}
+
+/// How well constructing `expr` gives back the value, once destructing the other pattern has
+/// found `known`
+fn rebuild(
+    expr: &Expression,
+    functions: &HashMap<LocalIntern<String>, Function>,
+    known: &HashSet<Name>,
+    lost: &mut HashSet<Name>,
+    reasons: &mut Vec<Reason>,
+) -> Invertibility {
+    let mut reason = |message: String| {
+        reasons.push(Reason {
+            invertibility: Invertibility::ForwardOnly,
+            message,
+            span: expr.span,
+        });
+        Invertibility::ForwardOnly
+    };
+    match &expr.data {
+        Expr::Ident(name) | Expr::PolyIdent(name) => {
+            let key = (*name, matches!(expr.data, Expr::PolyIdent(_)));
+            if known.contains(&key) || !lost.insert(key) {
+                Invertibility::Reversible
+            } else {
+                reason(format!(
+                    "`{}` isn't in the constructing pattern, so it is lost",
+                    name
+                ))
+            }
+        }
+        Expr::Any => reason("`_` throws away the value it matches".to_string()),
+        Expr::Call(f, _) if !functions.contains_key(f) => {
+            reason(format!("calls `{}`, which is not defined", f))
+        }
+        _ => children(expr)
+            .into_iter()
+            .map(|e| rebuild(e, functions, known, lost, reasons))
+            .max()
+            .unwrap_or(Invertibility::Reversible),
+    }
+}
+
+/// The expressions directly inside `expr`
+fn children(expr: &Expression) -> Vec<&Expression> {
+    match &expr.data {
+        Expr::Array(items) | Expr::Tuple(items) => items.iter().collect(),
+        Expr::Map(entries, rest) => entries
+            .iter()
+            .map(|(_, e)| e)
+            .chain(rest.as_deref())
+            .collect(),
+        Expr::Format(parts) => parts
+            .iter()
+            .filter_map(|p| match p {
+                FormatPart::Interpolation(e) => Some(e),
+                FormatPart::Text(_) => None,
+            })
+            .collect(),
+        Expr::Operator(_, left, right) => vec![left, right],
+        Expr::UnaryOp(_, e) | Expr::Cast(e, _, _) | Expr::Call(_, e) => vec![e],
+        Expr::Number(_)
+        | Expr::Int(_)
+        | Expr::String(_)
+        | Expr::Bool(_)
+        | Expr::Ident(_)
+        | Expr::PolyIdent(_)
+        | Expr::Any => vec![],
+    }
+}
+
+/// Whether every identifier in `expr` is known, so destructing it just compares it to the value
+fn is_known(expr: &Expression, known: &HashSet<Name>) -> bool {
+    match &expr.data {
+        Expr::Ident(name) => known.contains(&(*name, false)),
+        Expr::PolyIdent(_) | Expr::Any => false,
+        _ => children(expr).into_iter().all(|e| is_known(e, known)),
+    }
+}
+
+/// Whether `expr` is unknown and matches values of any length, unlike `[a, b]` or
+/// `[c]::#array~>#string`
+fn is_free(expr: &Expression, known: &HashSet<Name>) -> bool {
+    match &expr.data {
+        Expr::Array(_) | Expr::Tuple(_) => false,
+        Expr::Cast(inner, Type::String, Type::Array | Type::Tuple) => {
+            !matches!(inner.data, Expr::Array(_) | Expr::Tuple(_)) && !is_known(expr, known)
+        }
+        _ => !is_known(expr, known),
+    }
+}
+
+/// The terms of `a + b + ...`
+fn sum_parts<'e>(expr: &'e Expression, out: &mut Vec<&'e Expression>) {
+    match &expr.data {
+        Expr::Operator(Operator::Add, left, right) => {
+            sum_parts(left, out);
+            sum_parts(right, out);
+        }
+        _ => out.push(expr),
+    }
+}
+
+enum Kind {
+    Number,
+    /// A string, array or tuple
+    Sequence,
+}
+
+/// What the terms of a sum must be, going by the ones that make it clear
+fn kind(parts: &[&Expression]) -> Option<Kind> {
+    parts.iter().find_map(|p| match &p.data {
+        Expr::Number(_) | Expr::Int(_) | Expr::UnaryOp(UnaryOperator::Neg, _) => Some(Kind::Number),
+        Expr::Operator(Operator::Sub, ..) => Some(Kind::Number),
+        Expr::Cast(_, Type::Number, _) => Some(Kind::Number),
+        Expr::String(_) | Expr::Format(_) | Expr::Array(_) | Expr::Tuple(_) => Some(Kind::Sequence),
+        Expr::Cast(_, Type::String | Type::Array | Type::Tuple | Type::Radix(_), _) => {
+            Some(Kind::Sequence)
+        }
+        _ => None,
+    })
+}
+
+fn symbol(op: &Operator) -> &'static str {
+    match op {
+        Operator::Add => "+",
+        Operator::Sub => "-",
+        Operator::Mul => "*",
+        Operator::Div => "/",
+        Operator::And => "&&",
+        Operator::Or => "||",
+        Operator::Eq => "==",
+        Operator::Neq => "!=",
+        Operator::Lt => "<",
+        Operator::Gt => ">",
+        Operator::Le => "<=",
+        Operator::Ge => ">=",
+    }
+}
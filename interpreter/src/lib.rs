@@ -1,6 +1,9 @@
 #![allow(dead_code)]
 
+pub mod check;
 mod destruct_algebra;
 pub mod error;
 pub mod interpret;
+pub mod invertibility;
+pub mod number;
 pub mod traits;
@@ -0,0 +1,326 @@
+use std::{
+    cell::Cell,
+    cmp::Ordering,
+    fmt::Display,
+    ops::{Add, Mul, Neg, Sub},
+    str::FromStr,
+};
+
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use parser::num_bigint::BigInt;
+
+thread_local! {
+    static EXACT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Switches exact mode on or off for the current thread. In exact mode dividing integers gives a
+/// fraction instead of a float, and numbers written with a decimal point are read as the exact
+/// fraction they spell out, so all arithmetic can be reversed without any rounding.
+pub fn set_exact(exact: bool) {
+    EXACT.with(|e| e.set(exact))
+}
+
+pub fn is_exact() -> bool {
+    EXACT.with(|e| e.get())
+}
+
+/// A number, exact as long as it is whole. Integers stay integers through `+`, `-`, `*` and any
+/// division that leaves no remainder, anything else gives a float, or a fraction in exact mode.
+#[derive(Clone, Debug)]
+pub enum Number {
+    Int(BigInt),
+    /// Only made in exact mode, and never a whole number
+    Ratio(BigRational),
+    Float(f64),
+}
+
+impl Number {
+    /// The number written as the decimal `s`, like `12.5`, which in exact mode is read as the
+    /// exact fraction it spells out
+    pub fn from_decimal(s: &str) -> Number {
+        s.parse().unwrap_or(Number::Float(f64::NAN))
+    }
+
+    fn from_ratio(r: BigRational) -> Number {
+        if r.is_integer() {
+            Number::Int(r.to_integer())
+        } else {
+            Number::Ratio(r)
+        }
+    }
+
+    fn to_ratio(&self) -> Option<BigRational> {
+        match self {
+            Number::Int(i) => Some(BigRational::from_integer(i.clone())),
+            Number::Ratio(r) => Some(r.clone()),
+            Number::Float(f) => BigRational::from_float(*f),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let f = match self {
+            Number::Int(i) => i.to_f64(),
+            Number::Ratio(r) => r.to_f64(),
+            Number::Float(f) => return *f,
+        };
+        // too big for a float is infinitely big
+        f.unwrap_or(if self.is_negative() {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        })
+    }
+
+    fn is_negative(&self) -> bool {
+        match self {
+            Number::Int(i) => i.is_negative(),
+            Number::Ratio(r) => r.is_negative(),
+            Number::Float(f) => *f < 0.0,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Number::Int(i) => i.is_zero(),
+            Number::Ratio(_) => false,
+            Number::Float(f) => *f == 0.0,
+        }
+    }
+
+    /// The number as a count of repetitions, if it is whole and not negative
+    pub fn to_count(&self) -> Option<usize> {
+        match self {
+            Number::Int(i) => i.to_usize(),
+            Number::Float(f) if f.fract() == 0.0 && *f >= 0.0 => f.to_usize(),
+            Number::Ratio(_) | Number::Float(_) => None,
+        }
+    }
+
+    /// `self / other`, or `None` when dividing by zero in exact mode, where the infinity or NaN
+    /// it gives otherwise couldn't be told apart from a float
+    pub fn div(&self, other: &Number) -> Option<Number> {
+        Some(match (self, other) {
+            (_, b) if b.is_zero() && is_exact() => return None,
+            (_, b) if b.is_zero() => Number::Float(self.to_f64() / other.to_f64()),
+            (Number::Int(a), Number::Int(b)) if (a % b).is_zero() => Number::Int(a / b),
+            (Number::Int(_) | Number::Ratio(_), Number::Int(_) | Number::Ratio(_))
+                if is_exact() =>
+            {
+                Number::from_ratio(self.to_ratio().unwrap() / other.to_ratio().unwrap())
+            }
+            (a, b) => Number::Float(a.to_f64() / b.to_f64()),
+        })
+    }
+
+    /// The `x` for which `x * other` is `self`. Outside of exact mode integers only divide if there
+    /// is no remainder, since no integer multiplied by `other` could give `self` otherwise.
+    pub fn exact_div(&self, other: &Number) -> Option<Number> {
+        match (self, other) {
+            (_, b) if b.is_zero() => None,
+            (Number::Int(a), Number::Int(b)) if !is_exact() => {
+                if (a % b).is_zero() {
+                    Some(Number::Int(a / b))
+                } else {
+                    None
+                }
+            }
+            (a, b) => a.div(b),
+        }
+    }
+}
+
+impl From<usize> for Number {
+    fn from(n: usize) -> Self {
+        Number::Int(n.into())
+    }
+}
+
+/// Reads integers exactly and anything else as a float. In exact mode decimals and fractions like
+/// `1/3` are read exactly as well.
+impl FromStr for Number {
+    type Err = std::num::ParseFloatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(i) = s.parse::<BigInt>() {
+            return Ok(Number::Int(i));
+        }
+        if is_exact() {
+            if let Some(r) = parse_ratio(s) {
+                return Ok(Number::from_ratio(r));
+            }
+        }
+        s.parse().map(Number::Float)
+    }
+}
+
+/// Parses `n/d` or a decimal like `-12.5` as an exact fraction
+fn parse_ratio(s: &str) -> Option<BigRational> {
+    if let Some((n, d)) = s.split_once('/') {
+        let d = d.trim().parse::<BigInt>().ok()?;
+        if d.is_zero() {
+            return None;
+        }
+        return Some(BigRational::new(n.trim().parse().ok()?, d));
+    }
+    let (whole, fraction) = s.split_once('.')?;
+    if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let numerator = format!("{}{}", whole, fraction).parse::<BigInt>().ok()?;
+    let denominator = BigInt::from(10).pow(fraction.len() as u32);
+    Some(BigRational::new(numerator, denominator))
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Number::Int(i) => write!(f, "{}", i),
+            Number::Ratio(r) => match decimal(r) {
+                Some(d) => write!(f, "{}", d),
+                None => write!(f, "{}/{}", r.numer(), r.denom()),
+            },
+            Number::Float(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// `r` written out as a decimal, if it has a finite number of digits
+fn decimal(r: &BigRational) -> Option<String> {
+    // a fraction in lowest terms has a finite decimal iff its denominator only has factors 2 and 5
+    let mut denom = r.denom().clone();
+    let mut factors = [0u32; 2];
+    for (count, p) in factors.iter_mut().zip([2, 5]) {
+        let p = BigInt::from(p);
+        while (&denom % &p).is_zero() {
+            denom /= &p;
+            *count += 1;
+        }
+    }
+    if !denom.is_one() {
+        return None;
+    }
+    let digits = factors[0].max(factors[1]);
+    let scaled = (r * BigRational::from_integer(BigInt::from(10).pow(digits))).to_integer();
+    let magnitude = format!(
+        "{:0>width$}",
+        scaled.abs().to_string(),
+        width = digits as usize + 1
+    );
+    let (whole, fraction) = magnitude.split_at(magnitude.len() - digits as usize);
+    let sign = if scaled.is_negative() { "-" } else { "" };
+    Some(format!("{}{}.{}", sign, whole, fraction))
+}
+
+/// An integer and a float are equal if they are the same number
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Number::Int(a), Number::Int(b)) => a.partial_cmp(b),
+            (Number::Float(a), Number::Float(b)) => a.partial_cmp(b),
+            // compared exactly where possible, as floats lose precision on big integers
+            (a, b) => match (a.to_ratio(), b.to_ratio()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ => a.to_f64().partial_cmp(&b.to_f64()),
+            },
+        }
+    }
+}
+
+macro_rules! arithmetic {
+    ($trait:ident, $method:ident) => {
+        impl $trait for &Number {
+            type Output = Number;
+
+            fn $method(self, other: &Number) -> Number {
+                match (self, other) {
+                    (Number::Int(a), Number::Int(b)) => Number::Int(a.$method(b)),
+                    (Number::Float(_), _) | (_, Number::Float(_)) => {
+                        Number::Float(self.to_f64().$method(other.to_f64()))
+                    }
+                    (a, b) => {
+                        Number::from_ratio(a.to_ratio().unwrap().$method(b.to_ratio().unwrap()))
+                    }
+                }
+            }
+        }
+    };
+}
+
+arithmetic!(Add, add);
+arithmetic!(Sub, sub);
+arithmetic!(Mul, mul);
+
+impl Neg for &Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Int(i) => Number::Int(-i),
+            Number::Ratio(r) => Number::Ratio(-r),
+            Number::Float(f) => Number::Float(-f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(n: i64) -> Number {
+        Number::Int(n.into())
+    }
+
+    #[test]
+    fn integers_stay_integers() {
+        assert!(matches!(&int(2) + &int(3), Number::Int(n) if n == 5.into()));
+        assert!(matches!(&int(2) * &int(3), Number::Int(n) if n == 6.into()));
+        assert!(matches!(int(6).div(&int(3)), Some(Number::Int(n)) if n == 2.into()));
+        let big = "123456789012345678901234567890".parse::<Number>().unwrap();
+        assert_eq!(
+            (&big + &int(1)).to_string(),
+            "123456789012345678901234567891"
+        );
+    }
+
+    #[test]
+    fn inexact_results_become_floats() {
+        assert!(matches!(int(1).div(&int(2)), Some(Number::Float(f)) if f == 0.5));
+        assert!(matches!(&int(1) + &Number::from_decimal("0.5"), Number::Float(f) if f == 1.5));
+        assert!(matches!(int(1).div(&int(0)), Some(Number::Float(f)) if f.is_infinite()));
+        assert_eq!(int(3), Number::Float(3.0));
+    }
+
+    #[test]
+    fn exact_mode_keeps_fractions() {
+        set_exact(true);
+        assert!(matches!(int(1).div(&int(3)), Some(Number::Ratio(_))));
+        assert_eq!((&int(1).div(&int(3)).unwrap() * &int(3)).to_string(), "1");
+        let sum = &Number::from_decimal("0.1") + &Number::from_decimal("0.2");
+        assert_eq!(sum, Number::from_decimal("0.3"));
+        let big = Number::from_decimal("12345678901234567890.5");
+        assert_eq!(
+            (&big - &"12345678901234567890".parse().unwrap()).to_string(),
+            "0.5"
+        );
+        assert_eq!(
+            Number::from_decimal("0.12345678901234567890123").to_string(),
+            "0.12345678901234567890123"
+        );
+        assert!(int(1).div(&int(0)).is_none());
+        set_exact(false);
+    }
+
+    #[test]
+    fn exact_div_only_divides_without_remainder() {
+        assert!(int(7).exact_div(&int(2)).is_none());
+        assert!(matches!(int(8).exact_div(&int(2)), Some(Number::Int(n)) if n == 4.into()));
+        assert!(int(8).exact_div(&int(0)).is_none());
+    }
+}
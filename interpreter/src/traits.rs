@@ -1,26 +1,31 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Display,
+};
 
 use parser::{
-    ast::{Expr, Transformation},
+    ast::{Expression, Function},
     internment::LocalIntern,
+    parser::{is_identifier, quote},
 };
 
-use crate::error::RuntimeError;
+use crate::{error::RuntimeError, number::Number};
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Value {
-    Number(f64),
+    Number(Number),
     String(String),
     Array(Vec<Value>),
     Tuple(Vec<Value>),
     Bool(bool),
+    Map(BTreeMap<String, Value>),
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
             Value::Number(n) => write!(f, "{}", n),
-            Value::String(s) => write!(f, "\"{}\"", s),
+            Value::String(s) => write!(f, "{}", quote(s)),
             Value::Array(a) => {
                 write!(f, "[")?;
                 for (i, v) in a.iter().enumerate() {
@@ -42,6 +47,20 @@ impl Display for Value {
                 write!(f, ")")
             }
             Value::Bool(b) => write!(f, "{}", b),
+            Value::Map(m) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    if is_identifier(k) {
+                        write!(f, "{}: {}", k, v)?;
+                    } else {
+                        write!(f, "{}: {}", quote(k), v)?;
+                    }
+                }
+                write!(f, "}}")
+            }
         }
     }
 }
@@ -62,10 +81,58 @@ pub trait Maths {
 #[derive(Debug)]
 pub struct Variables {
     idents: HashMap<LocalIntern<String>, Value>,
-    pub polyidents: HashMap<LocalIntern<String>, Vec<Value>>,
+    /// The values of each polyident, in the order they are constructed, see
+    /// [`Variables::insert_polyident`]
+    pub polyidents: HashMap<LocalIntern<String>, VecDeque<Value>>,
+    /// The alternatives taken where the pattern being destructed could match in several ways
+    pub choices: Choices,
+    /// Set when an earlier attempt already failed, so the message of a mismatch is never shown,
+    /// see [`Variables::mismatch`]
+    pub quiet: bool,
+}
+
+/// The alternative picked at each point where a pattern could match in several ways, like where
+/// to split the string for `a + b`. When a pattern fails to match, it is destructed again with
+/// the next combination of alternatives, which is a depth first search with the earlier choice
+/// points changing the least often.
+#[derive(Debug, Default)]
+pub struct Choices {
+    /// The alternative picked and how many there were, for each choice point reached so far
+    path: Vec<(usize, usize)>,
+    /// How many choice points this attempt has reached
+    depth: usize,
+}
+
+impl Choices {
+    /// Which of `count` alternatives to take at the next choice point, in the order they should
+    /// be tried
+    pub fn choose(&mut self, count: usize) -> usize {
+        if self.depth == self.path.len() {
+            self.path.push((0, count));
+        }
+        let picked = self.path[self.depth].0;
+        self.depth += 1;
+        picked
+    }
+
+    /// Moves on to the next combination of alternatives to try, or returns false if there is
+    /// none left
+    pub fn advance(&mut self) -> bool {
+        // choice points past the last one reached were picked in an attempt that went differently
+        self.path.truncate(self.depth);
+        self.depth = 0;
+        while let Some((picked, count)) = self.path.last_mut() {
+            if *picked + 1 < *count {
+                *picked += 1;
+                return true;
+            }
+            self.path.pop();
+        }
+        false
+    }
 }
 
-pub type Functions = HashMap<LocalIntern<String>, Vec<Transformation>>;
+pub type Functions = HashMap<LocalIntern<String>, Function>;
 
 pub enum DestructResult {
     Known(Value),
@@ -76,40 +143,56 @@ pub enum DestructResult {
 #[derive(Clone)]
 pub enum PartialStringPart {
     String(String),
-    Expr(Expr),
+    /// A pattern for some of the text, with how many characters it has if that is known
+    Expr(Expression, Option<usize>),
 }
 
 pub enum PartialValue {
+    /// An array or tuple with some of its elements known
     Array {
         len: Option<usize>,
         known_elems: HashMap<usize, PartialValue>,
+        tuple: bool,
     },
-    //String(Vec<PartialStringPart>),
+    /// A string of known text with patterns for the text in between, like `a + ", " + b`
+    String(Vec<PartialStringPart>),
     Value(Value),
 }
 
 impl PartialValue {
     pub fn to_value(&self) -> Result<Value, RuntimeError> {
         match self {
-            PartialValue::Array { len, known_elems } => {
+            PartialValue::Array {
+                len,
+                known_elems,
+                tuple,
+            } => {
                 let len = len.ok_or(RuntimeError::ValueError(
                     "Array length is not known".to_string(),
+                    None,
                 ))?;
                 let mut vals = Vec::with_capacity(len);
                 for i in 0..len {
                     if let Some(val) = known_elems.get(&i) {
                         vals.push(val.to_value()?);
                     } else {
-                        return Err(RuntimeError::ValueError(format!(
-                            "Array element {} is not known",
-                            i
-                        )));
+                        return Err(RuntimeError::ValueError(
+                            format!("Array element {} is not known", i),
+                            None,
+                        ));
                     }
                 }
-                Ok(Value::Array(vals))
+                Ok(if *tuple {
+                    Value::Tuple(vals)
+                } else {
+                    Value::Array(vals)
+                })
             }
             PartialValue::Value(v) => Ok(v.clone()),
-            //PartialValue::String(_) => todo!(),
+            PartialValue::String(_) => Err(RuntimeError::ValueError(
+                "String is not fully known".to_string(),
+                None,
+            )),
         }
     }
 }
@@ -140,15 +223,28 @@ impl Variables {
         Variables {
             idents: HashMap::new(),
             polyidents: HashMap::new(),
+            choices: Choices::default(),
+            quiet: false,
         }
     }
+
+    /// A mismatch with the message made by `message`. While backtracking, only the first failed
+    /// attempt's message is kept, so for the rest this skips formatting values that could be as
+    /// big as the whole input.
+    pub fn mismatch(&self, message: impl FnOnce() -> String) -> RuntimeError {
+        let message = if self.quiet { String::new() } else { message() };
+        RuntimeError::PatternMismatch(message, None)
+    }
+
     pub fn insert(&mut self, key: LocalIntern<String>, value: Value) -> Result<(), RuntimeError> {
         if let Some(a) = self.idents.get(&key) {
             if a != &value {
-                Err(RuntimeError::ValueError(format!(
-                    "Variable {} already has a value different from {}",
-                    key, value
-                )))
+                Err(self.mismatch(|| {
+                    format!(
+                        "Variable {} already has a value different from {}",
+                        key, value
+                    )
+                }))
             } else {
                 Ok(())
             }
@@ -161,37 +257,41 @@ impl Variables {
         self.idents.get(&i)
     }
 
+    /// Adds a value of the polyident `key`. A polyident's values are one flat queue however
+    /// deeply its repetitions are nested: destructing adds them in the order the pattern visits
+    /// them, depth first and left to right, and constructing takes them in that same order. So
+    /// `[[*a] * m] * n` gives `*a` the values of the inner arrays one after the other, and
+    /// constructing `[*a] * (m * n)` flattens them, while `[[*a] * m] * n` nests them again.
     pub fn insert_polyident(
         &mut self,
         key: LocalIntern<String>,
         value: Value,
     ) -> Result<(), RuntimeError> {
-        if let Some(a) = self.polyidents.get_mut(&key) {
-            a.push(value);
-            Ok(())
-        } else {
-            self.polyidents.insert(key, vec![value]);
-            Ok(())
-        }
+        self.polyidents.entry(key).or_default().push_back(value);
+        Ok(())
     }
 
+    /// Takes the next value of the polyident `i`, see [`Variables::insert_polyident`] for the
+    /// order. `None` if the pattern never destructed it.
     pub fn take_polyident(
         &mut self,
         i: LocalIntern<String>,
     ) -> Result<Option<Value>, RuntimeError> {
-        if let Some(v) = self.polyidents.get_mut(&i) {
-            if v.is_empty() {
-                Err(RuntimeError::ValueError(format!(
-                    "Polyident {} is used up",
-                    i
-                )))
-            } else {
-                Ok(Some(v.remove(0)))
-            }
-        } else {
-            Ok(None)
+        match self.polyidents.get_mut(&i) {
+            Some(v) => v.pop_front().map(Some).ok_or_else(|| {
+                RuntimeError::ValueError(format!("Polyident {} is used up", i), None)
+            }),
+            None => Ok(None),
         }
     }
+
+    /// A polyident with values that constructing didn't use up, if any
+    pub fn leftover_polyident(&self) -> Option<LocalIntern<String>> {
+        self.polyidents
+            .iter()
+            .find(|(_, values)| !values.is_empty())
+            .map(|(name, _)| *name)
+    }
 }
 
 impl Default for Variables {
@@ -1,4 +1,5 @@
 use internment::LocalIntern;
+use num_bigint::BigInt;
 use std::str::FromStr;
 use std::{collections::HashMap, fmt};
 
@@ -7,18 +8,27 @@ use crate::{
     parser::{Lexer, Sp},
 };
 
-type Expression = Sp<Expr>;
+pub type Expression = Sp<Expr>;
+
+/// A function definition `name := ...;`, spanning from its name to the `;`
+pub type Function = Sp<Vec<Sp<Transformation>>>;
 
 #[derive(Debug)]
 pub enum Transformation {
     Forced {
-        destruct: Expr,
-        construct: Expr,
+        destruct: Expression,
+        construct: Expression,
+    },
+    /// `destruct => construct`, which constructs once for every way the pattern can match and
+    /// gives an array of the results
+    All {
+        destruct: Expression,
+        construct: Expression,
     },
-    Compound(Vec<Transformation>),
+    Compound(Vec<Sp<Transformation>>),
     Try {
-        first: Box<Transformation>,
-        otherwise: Box<Transformation>,
+        first: Box<Sp<Transformation>>,
+        otherwise: Box<Sp<Transformation>>,
     },
 }
 
@@ -29,6 +39,9 @@ pub enum Type {
     Tuple,
     Array,
     Bool,
+    Map,
+    /// A string of the digits of an integer in base 2, 8 or 16, like `"ff"` for `#hex`
+    Radix(u32),
 }
 
 impl fmt::Display for Type {
@@ -39,6 +52,11 @@ impl fmt::Display for Type {
             Type::Tuple => write!(f, "tuple"),
             Type::Array => write!(f, "array"),
             Type::Bool => write!(f, "bool"),
+            Type::Map => write!(f, "map"),
+            Type::Radix(2) => write!(f, "bin"),
+            Type::Radix(8) => write!(f, "oct"),
+            Type::Radix(16) => write!(f, "hex"),
+            Type::Radix(r) => write!(f, "base {}", r),
         }
     }
 }
@@ -53,6 +71,10 @@ impl FromStr for Type {
             "tuple" => Ok(Type::Tuple),
             "array" => Ok(Type::Array),
             "bool" => Ok(Type::Bool),
+            "map" => Ok(Type::Map),
+            "bin" => Ok(Type::Radix(2)),
+            "oct" => Ok(Type::Radix(8)),
+            "hex" => Ok(Type::Radix(16)),
             _ => Err(Self::Err::SyntaxError),
         }
     }
@@ -60,20 +82,33 @@ impl FromStr for Type {
 
 #[derive(Debug, Clone)]
 pub enum Expr {
-    Number(f64),
-    String(String, Option<StringFlag>),
-    Array(Vec<Expr>),
-    Tuple(Vec<Expr>),
+    /// A number with a decimal point, as written without any `_`
+    Number(String),
+    Int(BigInt),
+    String(String),
+    /// `f"Hello {name}!"`, text with the strings of expressions spliced in
+    Format(Vec<FormatPart>),
+    Array(Vec<Expression>),
+    Tuple(Vec<Expression>),
+    /// `{key: value, ..rest}`, where the rest is a map of every other key
+    Map(Vec<(String, Expression)>, Option<Box<Expression>>),
     Ident(LocalIntern<String>),
     PolyIdent(LocalIntern<String>),
-    Operator(Operator, Box<Expr>, Box<Expr>),
-    UnaryOp(UnaryOperator, Box<Expr>),
-    Cast(Box<Expr>, Type, Type),
+    Operator(Operator, Box<Expression>, Box<Expression>),
+    UnaryOp(UnaryOperator, Box<Expression>),
+    Cast(Box<Expression>, Type, Type),
     Bool(bool),
-    Call(LocalIntern<String>, Box<Expr>),
+    Call(LocalIntern<String>, Box<Expression>),
     Any, // _
 }
 
+#[derive(Debug, Clone)]
+pub enum FormatPart {
+    Text(String),
+    /// An expression in `{}`, which has to be a string
+    Interpolation(Expression),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum StringFlag {
     Format,
@@ -96,6 +131,35 @@ pub enum Operator {
     Ge,
 }
 
+impl Operator {
+    /// How tightly the operator binds, higher binds tighter. Every binary operator is
+    /// left-associative, so `10 - 3 - 2` is `(10 - 3) - 2`. Function calls, unary operators and
+    /// casts bind tighter than any binary operator, and a call's argument extends over casts, so
+    /// `f a::#number~>#string * 2` is `(f (a::#number~>#string)) * 2`.
+    ///
+    /// | precedence | operators                        |
+    /// | ---------- | -------------------------------- |
+    /// | 4          | `*`, `/`                         |
+    /// | 3          | `+`, `-`                         |
+    /// | 2          | `==`, `!=`, `<`, `>`, `<=`, `>=` |
+    /// | 1          | `&&`                             |
+    /// | 0          | `\|\|`                           |
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Operator::Mul | Operator::Div => 4,
+            Operator::Add | Operator::Sub => 3,
+            Operator::Eq
+            | Operator::Neq
+            | Operator::Lt
+            | Operator::Gt
+            | Operator::Le
+            | Operator::Ge => 2,
+            Operator::And => 1,
+            Operator::Or => 0,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum UnaryOperator {
     Neg,
@@ -104,7 +168,7 @@ pub enum UnaryOperator {
 
 #[derive(Debug)]
 pub struct TopLevel {
-    pub functions: HashMap<LocalIntern<String>, Vec<Transformation>>,
+    pub functions: HashMap<LocalIntern<String>, Function>,
 }
 
 impl FromStr for TopLevel {
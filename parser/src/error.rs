@@ -1,9 +1,12 @@
+use crate::parser::Span;
 use std::{error::Error, fmt::Display, path::PathBuf};
 
 #[derive(Debug)]
 pub enum LangError {
     SyntaxError {
+        /// 1-based line and column
         pos: (usize, usize),
+        span: Span,
         message: String,
         file: Option<PathBuf>,
     },
@@ -24,6 +27,7 @@ impl Display for LangError {
                 pos,
                 message,
                 file,
+                ..
             } => {
                 if let Some(file) = file {
                     write!(f, "Syntax Error: {}:{}:{}: {}", file.display(), pos.0, pos.1, message)
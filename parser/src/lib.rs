@@ -4,3 +4,4 @@ pub mod ast;
 pub mod error;
 pub mod parser;
 pub use internment;
+pub use num_bigint;
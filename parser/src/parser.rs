@@ -1,6 +1,10 @@
-use crate::ast::{Expr, Operator, StringFlag, TopLevel, Transformation, Type, UnaryOperator};
+use crate::ast::{
+    Expr, Expression, FormatPart, Operator, StringFlag, TopLevel, Transformation, Type,
+    UnaryOperator,
+};
 use crate::error::{LangError, LangErrorT};
 use logos::Logos;
+use num_bigint::BigInt;
 use std::collections::HashMap;
 use std::{
     fmt::{Debug, Display},
@@ -28,17 +32,75 @@ macro_rules! operator_pattern {
 
 #[derive(Clone)]
 pub struct Lexer<'a> {
-    pos: (usize, usize),
     tokens: logos::Lexer<'a, Tokens>,
     file: Option<PathBuf>,
 }
 
+/// Parses the digits of an integer literal, which may be separated by `_`
+fn parse_int(digits: &str, radix: u32) -> Option<BigInt> {
+    BigInt::parse_bytes(digits.replace('_', "").as_bytes(), radix)
+}
+
+/// Replaces the escape sequences in the contents of a string literal with the characters they
+/// stand for, or `None` if one of them isn't valid
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            'u' => {
+                let rest = chars.as_str().strip_prefix('{')?;
+                let (hex, rest) = rest.split_once('}')?;
+                chars = rest.chars();
+                char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+            }
+            _ => return None,
+        });
+    }
+    Some(out)
+}
+
+/// `s` as a string literal, quoted and with the characters that need it escaped
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Whether `s` can be written as an identifier, like a map key that needs no quotes
+pub fn is_identifier(s: &str) -> bool {
+    let mut lexer = Tokens::lexer(s);
+    matches!(lexer.next(), Some(Tokens::Ident(_))) && lexer.span().len() == s.len()
+}
+
 impl<'a> Lexer<'a> {
     pub fn new(content: &'a str, file: Option<PathBuf>) -> Self {
         let lexer = logos::Lexer::new(content);
         Self {
             tokens: lexer,
-            pos: (0, 0),
             file,
         }
     }
@@ -47,10 +109,8 @@ impl<'a> Lexer<'a> {
         let token = self.tokens.next()?;
 
         if token == Tokens::Newline {
-            self.pos.0 += 1; // :3
             self.next_token()
         } else {
-            self.pos.1 += self.tokens.span().len();
             Some(Token {
                 data: token,
                 span: self.tokens.span().into(),
@@ -58,11 +118,24 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Lexer that starts at byte `offset` of `content`, so spans stay relative to all of `content`
+    pub fn new_at(content: &'a str, offset: usize, file: Option<PathBuf>) -> Self {
+        let mut lexer = Self::new(content, file);
+        lexer.tokens.bump(offset);
+        lexer
+    }
+
+    /// Error pointing at the last consumed token
     pub fn err(&self, error: LangErrorT, message: &str) -> LangError {
+        self.err_at(self.tokens.span().into(), error, message)
+    }
+
+    pub fn err_at(&self, span: Span, error: LangErrorT, message: &str) -> LangError {
         match error {
             LangErrorT::SyntaxError => LangError::SyntaxError {
                 file: self.file.to_owned(),
-                pos: self.pos,
+                pos: span.position(self.tokens.source()),
+                span,
                 message: message.to_owned(),
             },
         }
@@ -71,29 +144,66 @@ impl<'a> Lexer<'a> {
     pub fn ensure_next(&mut self) -> Result<Token, LangError> {
         match self.next_token() {
             Some(t) => Ok(t),
-            None => Err(self.err(LangErrorT::SyntaxError, "Unexpected end of input")),
+            None => Err(self.err_at(
+                self.eof_span(),
+                LangErrorT::SyntaxError,
+                "Unexpected end of input",
+            )),
         }
     }
 
+    /// Parses the whole input, stopping at the first syntax error
     pub fn parse(&mut self) -> Result<TopLevel, LangError> {
+        let (top_level, errors) = self.parse_recovering();
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(top_level),
+        }
+    }
+
+    /// Parses the whole input, skipping to the next `|` or `;` after a syntax error so every
+    /// error in the input is reported. Functions keep the transformations that did parse, so the
+    /// [`TopLevel`] is only fit to run if there are no errors.
+    pub fn parse_recovering(&mut self) -> (TopLevel, Vec<LangError>) {
         let mut functions = HashMap::new();
+        let mut errors = Vec::new();
 
         loop {
-            let name = match self.next_token() {
+            let (name, start) = match self.next_token() {
                 Some(Token {
                     data: Tokens::Ident(i),
-                    ..
-                }) => i,
-                Some(_) => return Err(self.err(LangErrorT::SyntaxError, "Expected function name")),
+                    span,
+                }) => (i, span.start),
+                Some(_) => {
+                    errors.push(self.err(LangErrorT::SyntaxError, "Expected function name"));
+                    self.skip_function();
+                    continue;
+                }
                 None => break,
             };
 
-            self.expect(Tokens::Define)?;
+            if let Err(e) = self.expect(Tokens::Define) {
+                errors.push(e);
+                self.skip_function();
+                continue;
+            }
 
             let mut transformations = Vec::new();
 
             loop {
-                transformations.push(self.parse_transform()?);
+                let checkpoint = self.clone();
+                match self.parse_transform() {
+                    Ok(t) => transformations.push(t),
+                    Err(e) => {
+                        errors.push(e);
+                        // the failed transformation may have opened braces, so skip it as a whole
+                        *self = checkpoint;
+                        self.synchronise();
+                        if self.peek().is_none() {
+                            break;
+                        }
+                    }
+                }
 
                 match self.next_token() {
                     Some(Token {
@@ -102,77 +212,268 @@ impl<'a> Lexer<'a> {
                     Some(Token {
                         data: Tokens::Pipe, ..
                     }) => (),
-                    a => {
-                        return Err(self.err(
+                    Some(Token { data, .. }) => {
+                        errors.push(self.err(
                             LangErrorT::SyntaxError,
-                            &format!("Expected ';' or '|', found {:?}", a),
-                        ))
+                            &format!("Expected `;` or `|`, found `{}`", data),
+                        ));
+                        self.synchronise();
+                        // the function goes on after a `|`, and ends at a `;` or the end of input
+                        if self.next_token().map(|t| t.data) != Some(Tokens::Pipe) {
+                            break;
+                        }
+                    }
+                    None => {
+                        errors.push(self.err_at(
+                            self.eof_span(),
+                            LangErrorT::SyntaxError,
+                            "Expected `;` or `|`",
+                        ));
+                        break;
                     }
                 }
             }
-            functions.insert(name, transformations);
+            functions.insert(name, Sp::new(transformations, self.span_from(start)));
+        }
+
+        (TopLevel { functions }, errors)
+    }
+
+    /// Parses transformations chained with `|` up to the end of the input, like the body of a
+    /// function without its name. The closing `;` is optional.
+    pub fn parse_pipeline(&mut self) -> Result<Vec<Sp<Transformation>>, LangError> {
+        let mut transformations = Vec::new();
+        loop {
+            transformations.push(self.parse_transform()?);
+            match self.next_token() {
+                Some(Token {
+                    data: Tokens::Pipe, ..
+                }) => (),
+                Some(Token {
+                    data: Tokens::Semi, ..
+                })
+                | None => break,
+                Some(Token { data, .. }) => {
+                    return Err(self.err(
+                        LangErrorT::SyntaxError,
+                        &format!("Expected `;` or `|`, found `{}`", data),
+                    ))
+                }
+            }
+        }
+        match self.next_token() {
+            Some(Token { data, .. }) => Err(self.err(
+                LangErrorT::SyntaxError,
+                &format!("Expected end of input, found `{}`", data),
+            )),
+            None => Ok(transformations),
+        }
+    }
+
+    /// Parses a single expression spanning the whole input, like a literal given as a value
+    pub fn parse_expression(&mut self) -> Result<Expression, LangError> {
+        let expr = self.parse_expr()?;
+        match self.next_token() {
+            Some(Token { data, .. }) => Err(self.err(
+                LangErrorT::SyntaxError,
+                &format!("Expected end of input, found `{}`", data),
+            )),
+            None => Ok(expr),
+        }
+    }
+
+    /// Skips tokens up to the next `|` or `;` that could end the current transformation, leaving
+    /// it as the next token. A `|` inside braces opened while skipping belongs to a compound
+    /// transformation, but a `;` always ends the function.
+    fn synchronise(&mut self) {
+        let mut depth = 0usize;
+        while let Some(Token { data, .. }) = self.peek() {
+            match data {
+                Tokens::Semi => return,
+                Tokens::Pipe if depth == 0 => return,
+                Tokens::Lbrace => depth += 1,
+                Tokens::Rbrace => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            self.next_token();
         }
+    }
 
-        Ok(TopLevel { functions })
+    /// Skips past the next `;`, after a function header that could not be parsed
+    fn skip_function(&mut self) {
+        while let Some(Token { data, .. }) = self.next_token() {
+            if data == Tokens::Semi {
+                break;
+            }
+        }
     }
 
-    fn parse_maths(&mut self, operator: Tokens, lhs: Expr, rhs: Expr) -> Result<Expr, LangError> {
+    fn parse_maths(
+        &mut self,
+        operator: Tokens,
+        lhs: Expression,
+        rhs: Expression,
+    ) -> Result<Expression, LangError> {
         match operator {
             op @ operator_pattern!() => {
+                let span = Span::new(lhs.span.start, rhs.span.end);
                 let lhs = Box::new(lhs);
                 let rhs = Box::new(rhs);
-                Ok(Expr::Operator(op.into(), lhs, rhs))
+                Ok(Sp::new(Expr::Operator(op.into(), lhs, rhs), span))
             }
 
             t => Err(self.err(
                 LangErrorT::SyntaxError,
-                &format!("Expected operator, found {:?}", t),
+                &format!("Expected operator, found `{}`", t),
             )),
         }
     }
 
+    /// Span from `start` to the end of the last consumed token
+    fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.tokens.span().end)
+    }
+
+    /// Start of the next token, or the end of the input if there is none
+    fn next_start(&self) -> usize {
+        match self.peek() {
+            Some(t) => t.span.start,
+            None => self.tokens.source().len(),
+        }
+    }
+
+    /// Empty span at the end of the input
+    fn eof_span(&self) -> Span {
+        let len = self.tokens.source().len();
+        Span::new(len, len)
+    }
+
     fn expect(&mut self, token: Tokens) -> Result<(), LangError> {
         match self.peek() {
             Some(Token { data: t, .. }) if t == token => self.next_token(),
-            Some(Token { data: t, .. }) => {
-                return Err(self.err(
+            Some(Token { data: t, span }) => {
+                return Err(self.err_at(
+                    span,
+                    LangErrorT::SyntaxError,
+                    &format!("Expected `{}`, found `{}`", token, t),
+                ))
+            }
+            None => {
+                return Err(self.err_at(
+                    self.eof_span(),
                     LangErrorT::SyntaxError,
-                    &format!("Expected {:?}, found {:?}", token, t),
+                    &format!("Expected `{}`", token),
                 ))
             }
-            None => return Err(self.err(LangErrorT::SyntaxError, &format!("Expected {:?}", token))),
         };
         Ok(())
     }
 
-    fn parse_expr(&mut self) -> Result<Expr, LangError> {
+    /// Parses a full expression, see [`Operator::precedence`] for how operators bind
+    fn parse_expr(&mut self) -> Result<Expression, LangError> {
+        self.parse_binary(0)
+    }
+
+    // precedence climbing: only operators binding at least as tight as `min_prec` are consumed here,
+    // and the right hand side must bind strictly tighter, which makes every operator left-associative
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expression, LangError> {
+        let mut lhs = self.parse_cast()?;
+
+        while let Some(Token {
+            data: operator @ operator_pattern!(),
+            ..
+        }) = self.peek()
+        {
+            let prec = Operator::from(operator.clone()).precedence();
+            if prec < min_prec {
+                break;
+            }
+            self.next_token();
+            let rhs = self.parse_binary(prec + 1)?;
+            lhs = self.parse_maths(operator, lhs, rhs)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_cast(&mut self) -> Result<Expression, LangError> {
+        let mut expr = self.parse_unary()?;
+
+        while let Some(Token {
+            data: Tokens::DoubleColon,
+            ..
+        }) = self.peek()
+        {
+            // cast
+            // v::#from ~> #to
+            self.next_token();
+            let from = self.parse_type()?;
+            self.expect(Tokens::WavyArrow)?;
+            let to = self.parse_type()?;
+
+            let span = self.span_from(expr.span.start);
+            expr = Sp::new(Expr::Cast(Box::new(expr), to, from), span);
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_type(&mut self) -> Result<Type, LangError> {
+        match self.ensure_next()?.data {
+            Tokens::Type(s) => {
+                let mut s2 = s.to_string();
+                s2.remove(0);
+                match s2.parse::<Type>() {
+                    Err(e) => Err(self.err(e, &format!("`{}` is not a valid type", s))),
+                    Ok(t) => Ok(t),
+                }
+            }
+            token => Err(self.err(
+                LangErrorT::SyntaxError,
+                &format!("Expected type, found `{}`", token),
+            )),
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, LangError> {
+        let start = self.next_start();
         let unary_operator = match self.peek() {
             Some(Token {
                 data: Tokens::Minus,
                 ..
-            }) => {
-                self.next_token();
-                Some(UnaryOperator::Neg)
-            }
+            }) => UnaryOperator::Neg,
             Some(Token {
                 data: Tokens::Exclamation,
                 ..
-            }) => {
-                self.next_token();
-                Some(UnaryOperator::Not)
-            }
-            _ => None,
+            }) => UnaryOperator::Not,
+            _ => return self.parse_primary(),
         };
+        self.next_token();
+
+        let operand = self.parse_unary()?;
+        Ok(Sp::new(
+            Expr::UnaryOp(unary_operator, Box::new(operand)),
+            self.span_from(start),
+        ))
+    }
 
-        let first = match self.ensure_next()?.data {
+    fn parse_primary(&mut self) -> Result<Expression, LangError> {
+        let first = self.ensure_next()?;
+        let expr = match first.data {
             Tokens::Number(n) => Expr::Number(n),
+            Tokens::Int(n) => Expr::Int(n),
             Tokens::False => Expr::Bool(false),
             Tokens::True => Expr::Bool(true),
-            Tokens::StringLiteral(mut s) => {
-                let flag = s.1;
-                s.0.remove(0);
-                s.0.pop();
-                Expr::String(s.0, flag)
+            Tokens::StringLiteral((s, None)) => Expr::String(s),
+            Tokens::StringLiteral((_, Some(StringFlag::Format))) => {
+                Expr::Format(self.parse_format(first.span.start)?)
+            }
+            // strings only fail to lex because of a bad escape, as the regex matches any other string
+            Tokens::Error if self.tokens.slice().ends_with('"') => {
+                return Err(self.err(
+                    LangErrorT::SyntaxError,
+                    "Invalid escape sequence in string, expected one of \\n, \\t, \\r, \\0, \\\\, \\\" or \\u{...}",
+                ))
             }
             Tokens::Lbracket => {
                 // check for immidiate right bracket
@@ -194,7 +495,7 @@ impl<'a> Lexer<'a> {
                             token => {
                                 return Err(self.err(
                                     LangErrorT::SyntaxError,
-                                    &format!("Expected tokens `]` or `,`, found {:?}", token),
+                                    &format!("Expected tokens `]` or `,`, found `{}`", token),
                                 ))
                             }
                         }
@@ -203,6 +504,8 @@ impl<'a> Lexer<'a> {
                 }
             }
 
+            Tokens::Lbrace => self.parse_map()?,
+
             Tokens::Ident(s) => match self.peek() {
                 Some(Token {
                     data:
@@ -214,11 +517,14 @@ impl<'a> Lexer<'a> {
                         | Tokens::Pipe
                         | Tokens::Rbrace
                         | Tokens::Rarrow
+                        | Tokens::FatArrow
                         | Tokens::DoubleColon
                         | Tokens::Colon
                         | Tokens::Question,
                     ..
-                }) => Expr::Ident(s),
+                })
+                | None => Expr::Ident(s),
+                // the argument is the rest of the expression, so `f a + b` is `f (a + b)`
                 _ => Expr::Call(s, self.parse_expr()?.into()),
             },
             Tokens::Star => {
@@ -232,6 +538,18 @@ impl<'a> Lexer<'a> {
             Tokens::Underscore => Expr::Any,
 
             Tokens::Lparen => {
+                // `()` is the empty tuple
+                if let Some(Token {
+                    data: Tokens::Rparen,
+                    ..
+                }) = self.peek()
+                {
+                    self.next_token();
+                    return Ok(Sp::new(
+                        Expr::Tuple(Vec::new()),
+                        self.span_from(first.span.start),
+                    ));
+                }
                 let expr = self.parse_expr()?;
                 if let Some(Token {
                     data: Tokens::Rparen,
@@ -239,11 +557,23 @@ impl<'a> Lexer<'a> {
                 }) = self.peek()
                 {
                     self.next_token();
-                    expr
+                    // parentheses only group, the expression keeps its own span
+                    return Ok(expr);
                 } else {
                     self.expect(Tokens::Comma)?;
                     let mut exprs = vec![expr];
                     loop {
+                        // a trailing comma makes `(a,)` a tuple of one element
+                        if exprs.len() == 1 {
+                            if let Some(Token {
+                                data: Tokens::Rparen,
+                                ..
+                            }) = self.peek()
+                            {
+                                self.next_token();
+                                break;
+                            }
+                        }
                         exprs.push(self.parse_expr()?);
                         match self.ensure_next()?.data {
                             Tokens::Comma => (),
@@ -252,7 +582,7 @@ impl<'a> Lexer<'a> {
                             token => {
                                 return Err(self.err(
                                     LangErrorT::SyntaxError,
-                                    &format!("Expected tokens `)` or `,`, found {:?}", token),
+                                    &format!("Expected tokens `)` or `,`, found `{}`", token),
                                 ))
                             }
                         }
@@ -264,86 +594,171 @@ impl<'a> Lexer<'a> {
             token => {
                 return Err(self.err(
                     LangErrorT::SyntaxError,
-                    &format!("Unexpected token: {:?}", token),
+                    &format!("Unexpected token `{}`", token),
                 ))
             }
         };
+        Ok(Sp::new(expr, self.span_from(first.span.start)))
+    }
 
-        let first = if let Some(uo) = unary_operator {
-            Expr::UnaryOp(uo, Box::new(first))
-        } else {
-            first
-        };
-
-        match self.peek() {
-            Some(Token {
-                data: operator @ operator_pattern!(),
-                ..
-            }) => {
-                self.next_token();
-                let rhs = self.parse_expr()?;
-                self.parse_maths(operator, first, rhs)
-            }
-
-            Some(Token {
-                data: Tokens::DoubleColon,
-                ..
-            }) => {
-                // cast
-                // v::#from ~> #to
-                self.next_token();
-                let from = match self.ensure_next()?.data {
-                    Tokens::Type(s) => {
-                        let mut s2 = s.to_string();
-                        s2.remove(0);
-                        match s2.parse::<Type>() {
-                            Err(e) => {
-                                return Err(self.err(e, &format!("{:?} is not a valid type", s)))
+    /// Splits the format string that was just lexed, starting at `start`, into its text and the
+    /// expressions in `{}`. `{{` and `}}` stand for literal braces.
+    fn parse_format(&self, start: usize) -> Result<Vec<FormatPart>, LangError> {
+        let raw = self.tokens.slice();
+        // without the `f"` and the closing `"`
+        let content = &raw[2..raw.len() - 1];
+        let offset = start + 2;
+        let mut parts = Vec::new();
+        // still escaped, the lexer has already checked that the escapes are valid
+        let mut text = String::new();
+        let mut chars = content.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    // copied whole so the braces in `\u{...}` aren't taken for an interpolation
+                    text.push(c);
+                    if let Some((_, c)) = chars.next() {
+                        text.push(c);
+                        if c == 'u' {
+                            for (_, c) in chars.by_ref() {
+                                text.push(c);
+                                if c == '}' {
+                                    break;
+                                }
                             }
-                            Ok(t) => t,
                         }
                     }
-                    token => {
-                        return Err(self.err(
-                            LangErrorT::SyntaxError,
-                            &format!("Expected type, found {:?}", token),
-                        ))
-                    }
-                };
-
-                self.expect(Tokens::WavyArrow)?;
-
-                let to = match self.ensure_next()?.data {
-                    Tokens::Type(s) => {
-                        let mut s2 = s.to_string();
-                        s2.remove(0);
-                        match s2.parse::<Type>() {
-                            Err(e) => {
-                                return Err(self.err(e, &format!("{:?} is not a valid type", s)))
+                }
+                '{' | '}' if chars.peek().map(|(_, next)| *next) == Some(c) => {
+                    chars.next();
+                    text.push(c);
+                }
+                '}' => {
+                    return Err(self.err_at(
+                        Span::new(offset + i, offset + i + 1),
+                        LangErrorT::SyntaxError,
+                        "Unmatched `}` in format string, write `}}` for a literal `}`",
+                    ))
+                }
+                '{' => {
+                    let mut depth = 0usize;
+                    let end =
+                        loop {
+                            match chars.next() {
+                                Some((_, '{')) => depth += 1,
+                                Some((j, '}')) if depth == 0 => break j,
+                                Some((_, '}')) => depth -= 1,
+                                Some(_) => (),
+                                None => return Err(self.err_at(
+                                    Span::new(offset + i, offset + i + 1),
+                                    LangErrorT::SyntaxError,
+                                    "Unclosed `{` in format string, write `{{` for a literal `{`",
+                                )),
                             }
-                            Ok(t) => t,
-                        }
+                        };
+                    if !text.is_empty() {
+                        parts.push(FormatPart::Text(
+                            unescape(&std::mem::take(&mut text)).expect("escapes were checked"),
+                        ));
                     }
-                    token => {
-                        return Err(self.err(
-                            LangErrorT::SyntaxError,
-                            &format!("Expected type, found {:?}", token),
-                        ))
-                    }
-                };
+                    // lexing only up to the `}` makes the expression end there
+                    let mut lexer = Lexer::new_at(
+                        &self.tokens.source()[..offset + end],
+                        offset + i + 1,
+                        self.file.clone(),
+                    );
+                    parts.push(FormatPart::Interpolation(lexer.parse_expression()?));
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(FormatPart::Text(
+                unescape(&text).expect("escapes were checked"),
+            ));
+        }
+        Ok(parts)
+    }
 
-                Ok(Expr::Cast(Box::new(first), to, from))
+    /// Parses the entries of a map after its `{`
+    fn parse_map(&mut self) -> Result<Expr, LangError> {
+        let mut entries: Vec<(String, Expression)> = Vec::new();
+        let mut rest = None;
+        loop {
+            let token = self.ensure_next()?;
+            let key = match token.data {
+                // `{}`, or a trailing comma
+                Tokens::Rbrace => break,
+                Tokens::Dot => {
+                    self.expect(Tokens::Dot)?;
+                    let pattern = match self.peek() {
+                        // `..` on its own ignores the other keys, like `.._`
+                        Some(Token {
+                            data: Tokens::Rbrace,
+                            ..
+                        }) => Sp::new(Expr::Any, self.span_from(token.span.start)),
+                        _ => self.parse_expr()?,
+                    };
+                    rest = Some(Box::new(pattern));
+                    self.expect(Tokens::Rbrace)?;
+                    break;
+                }
+                Tokens::Ident(key) => key.to_string(),
+                Tokens::StringLiteral((key, None)) => key,
+                t => {
+                    return Err(self.err(
+                        LangErrorT::SyntaxError,
+                        &format!("Expected a key, `..` or `}}`, found `{}`", t),
+                    ))
+                }
+            };
+            if entries.iter().any(|(k, _)| *k == key) {
+                return Err(self.err(
+                    LangErrorT::SyntaxError,
+                    &format!("Key `{}` appears more than once", key),
+                ));
+            }
+            self.expect(Tokens::Colon)?;
+            entries.push((key, self.parse_expr()?));
+
+            match self.ensure_next()?.data {
+                Tokens::Comma => (),
+                Tokens::Rbrace => break,
+                token => {
+                    return Err(self.err(
+                        LangErrorT::SyntaxError,
+                        &format!("Expected tokens `}}` or `,`, found `{}`", token),
+                    ))
+                }
             }
-            _ => Ok(first),
         }
+        Ok(Expr::Map(entries, rest))
     }
 
-    pub fn parse_transform(&mut self) -> Result<Transformation, LangError> {
-        match self.peek() {
+    /// Whether a `{` starting a transformation opens a map rather than a compound transformation
+    fn map_ahead(&self) -> bool {
+        matches!(
+            self.peek_many(3)
+                .iter()
+                .map(|t| &t.data)
+                .collect::<Vec<_>>()[..],
+            [Tokens::Lbrace, Tokens::Rbrace, ..]
+                | [Tokens::Lbrace, Tokens::Dot, ..]
+                | [
+                    Tokens::Lbrace,
+                    Tokens::Ident(_) | Tokens::StringLiteral(_),
+                    Tokens::Colon
+                ]
+        )
+    }
+
+    pub fn parse_transform(&mut self) -> Result<Sp<Transformation>, LangError> {
+        let start = self.next_start();
+        let transform = match self.peek() {
             Some(Token {
                 data: Tokens::Lbrace,
                 ..
-            }) => {
+            }) if !self.map_ahead() => {
                 self.next_token();
                 let mut transforms = Vec::new();
                 loop {
@@ -355,12 +770,12 @@ impl<'a> Lexer<'a> {
                         token => {
                             return Err(self.err(
                                 LangErrorT::SyntaxError,
-                                &format!("Expected tokens `}}` or `|`, found {:?}", token),
+                                &format!("Expected tokens `}}` or `|`, found `{}`", token),
                             ))
                         }
                     }
                 }
-                Ok(Transformation::Compound(transforms))
+                Transformation::Compound(transforms)
             }
 
             // if ?, make try transform
@@ -372,19 +787,30 @@ impl<'a> Lexer<'a> {
                 let first = Box::new(self.parse_transform()?);
                 self.expect(Tokens::Colon)?;
                 let otherwise = Box::new(self.parse_transform()?);
-                Ok(Transformation::Try { first, otherwise })
+                Transformation::Try { first, otherwise }
             }
 
             _ => {
                 let destruct = self.parse_expr()?;
-                self.expect(Tokens::Rarrow)?;
-                let construct = self.parse_expr()?;
-                Ok(Transformation::Forced {
-                    destruct,
-                    construct,
-                })
+                match self.ensure_next()?.data {
+                    Tokens::Rarrow => Transformation::Forced {
+                        destruct,
+                        construct: self.parse_expr()?,
+                    },
+                    Tokens::FatArrow => Transformation::All {
+                        destruct,
+                        construct: self.parse_expr()?,
+                    },
+                    token => {
+                        return Err(self.err(
+                            LangErrorT::SyntaxError,
+                            &format!("Expected tokens `->` or `=>`, found `{}`", token),
+                        ))
+                    }
+                }
             }
-        }
+        };
+        Ok(Sp::new(transform, self.span_from(start)))
     }
 
     pub fn peek(&self) -> Option<Token> {
@@ -424,8 +850,9 @@ impl<'a> Lexer<'a> {
         out
     }
 
+    /// Line and column of the last consumed token
     pub fn pos(&self) -> (usize, usize) {
-        self.pos
+        Span::from(self.tokens.span()).position(self.tokens.source())
     }
 
     pub fn file(&self) -> Option<PathBuf> {
@@ -435,8 +862,14 @@ impl<'a> Lexer<'a> {
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub struct Sp<T> {
-    data: T,
-    span: Span,
+    pub data: T,
+    pub span: Span,
+}
+
+impl<T> Sp<T> {
+    pub fn new(data: T, span: Span) -> Self {
+        Self { data, span }
+    }
 }
 
 impl Default for Token {
@@ -450,14 +883,22 @@ impl Default for Token {
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub struct Span {
-    start: usize,
-    end: usize,
+    pub start: usize,
+    pub end: usize,
 }
 
 impl Span {
     pub fn new(start: usize, end: usize) -> Self {
         Self { start, end }
     }
+
+    /// 1-based line and column of the start of the span in `source`, counted in characters
+    pub fn position(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (line, before[line_start..].chars().count() + 1)
+    }
 }
 
 impl From<logos::Span> for Span {
@@ -476,9 +917,10 @@ impl From<Tokens> for Expr {
     fn from(t: Tokens) -> Self {
         match t {
             // ill add the thingy thingy at Tokens ok your
-            Tokens::StringLiteral(s) => Expr::String(s.0, s.1),
+            Tokens::StringLiteral((s, None)) => Expr::String(s),
             Tokens::Ident(i) => Expr::Ident(i),
             Tokens::Number(n) => Expr::Number(n),
+            Tokens::Int(n) => Expr::Int(n),
 
             _ => panic!(),
         }
@@ -573,6 +1015,9 @@ pub enum Tokens {
     #[token("->")]
     Rarrow,
 
+    #[token("=>")]
+    FatArrow,
+
     #[token("~>")]
     WavyArrow,
 
@@ -620,22 +1065,19 @@ pub enum Tokens {
         } else {
             None
         };
-        (s, flag)
+        unescape(&s[1..s.len() - 1]).map(|s| (s, flag))
     })]
     StringLiteral((String, Option<StringFlag>)),
 
-    #[regex(r"([0-9][0-9_]*(\.[0-9_]+)?)", |lex| lex.slice().parse())]
-    // like here // where does the error go // Err token // a
-    Number(f64),
+    #[regex(r"[0-9][0-9_]*", |lex| parse_int(lex.slice(), 10))]
+    #[regex("0b[01](_?[01]+)*", |lex| parse_int(&lex.slice()[2..], 2))]
+    #[regex("0o[0-7](_?[0-7]+)*", |lex| parse_int(&lex.slice()[2..], 8))]
+    #[regex("0x[a-fA-F0-9](_?[a-fA-F0-9]+)*", |lex| parse_int(&lex.slice()[2..], 16))]
+    Int(BigInt),
 
-    #[regex("0b[01](_?[01]+)*")]
-    BinaryLiteral,
-
-    #[regex("0x[a-fA-F0-9](_?[a-fA-F0-9]+)*")]
-    HexLiteral,
-
-    #[regex("0o[0-7](_?[0-7]+)*")]
-    OctalLiteral,
+    // kept as written, so exact mode can read it as the exact fraction it spells out
+    #[regex(r"([0-9][0-9_]*\.[0-9_]+)", |lex| lex.slice().replace('_', ""))]
+    Number(String),
 
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| LocalIntern::new(lex.slice().to_owned()))]
     Ident(LocalIntern<String>),
@@ -650,3 +1092,152 @@ pub enum Tokens {
     #[regex(r"[ \t\f\r]+|/\*[^*]*\*(([^/\*][^\*]*)?\*)*/|//[^\n]*", logos::skip)]
     Error,
 }
+
+/// Shows the token as it is written in the source
+impl Display for Tokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Tokens::Star => "*",
+            Tokens::Plus => "+",
+            Tokens::Minus => "-",
+            Tokens::Fslash => "/",
+            Tokens::And => "&&",
+            Tokens::Or => "||",
+            Tokens::Eq => "==",
+            Tokens::Neq => "!=",
+            Tokens::Lt => "<",
+            Tokens::Le => "<=",
+            Tokens::Gt => ">",
+            Tokens::Ge => ">=",
+            Tokens::Underscore => "_",
+            Tokens::Lbracket => "[",
+            Tokens::Rbracket => "]",
+            Tokens::Lparen => "(",
+            Tokens::Rparen => ")",
+            Tokens::Lbrace => "{",
+            Tokens::Rbrace => "}",
+            Tokens::Larrow => "<-",
+            Tokens::Rarrow => "->",
+            Tokens::FatArrow => "=>",
+            Tokens::WavyArrow => "~>",
+            Tokens::Pipe => "|",
+            Tokens::Semi => ";",
+            Tokens::Colon => ":",
+            Tokens::DoubleColon => "::",
+            Tokens::Comma => ",",
+            Tokens::Dot => ".",
+            Tokens::Question => "?",
+            Tokens::Exclamation => "!",
+            Tokens::Define => ":=",
+            Tokens::True => "true",
+            Tokens::False => "false",
+            Tokens::StringLiteral((s, None)) => return write!(f, "{:?}", s),
+            Tokens::StringLiteral((s, Some(StringFlag::Format))) => return write!(f, "f{:?}", s),
+            Tokens::Int(n) => return write!(f, "{}", n),
+            Tokens::Number(n) => n,
+            Tokens::Ident(name) | Tokens::Type(name) => return write!(f, "{}", name),
+            Tokens::Newline => "\\n",
+            Tokens::Error => "<invalid token>",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `expr` with brackets around every operator, like `(1 Add (2 Mul 3))`
+    fn shape(expr: &Expression) -> String {
+        match &expr.data {
+            Expr::Operator(op, l, r) => format!("({} {:?} {})", shape(l), op, shape(r)),
+            Expr::UnaryOp(op, e) => format!("({:?} {})", op, shape(e)),
+            Expr::Cast(e, to, from) => format!("({} :: {} ~> {})", shape(e), from, to),
+            Expr::Call(f, e) => format!("({} {})", f, shape(e)),
+            Expr::Ident(i) => i.to_string(),
+            Expr::Int(n) => n.to_string(),
+            Expr::Number(n) => n.clone(),
+            e => format!("{:?}", e),
+        }
+    }
+
+    fn parse(source: &str) -> String {
+        shape(&Lexer::new(source, None).parse_expression().unwrap())
+    }
+
+    fn syntax_error(source: &str) -> String {
+        match Lexer::new(source, None).parse() {
+            Ok(_) => panic!("{:?} parsed", source),
+            Err(LangError::SyntaxError { message, .. }) => message,
+        }
+    }
+
+    #[test]
+    fn operators_bind_by_precedence() {
+        assert_eq!(parse("1 + 2 * 3"), "(1 Add (2 Mul 3))");
+        assert_eq!(parse("1 * 2 + 3"), "((1 Mul 2) Add 3)");
+        assert_eq!(
+            parse("a + 1 == b && c || d"),
+            "((((a Add 1) Eq b) And c) Or d)"
+        );
+        assert_eq!(parse("a || b && c"), "(a Or (b And c))");
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        assert_eq!(parse("10 - 3 - 2"), "((10 Sub 3) Sub 2)");
+        assert_eq!(parse("8 / 4 / 2"), "((8 Div 4) Div 2)");
+    }
+
+    #[test]
+    fn unary_operators_and_casts_bind_tightest() {
+        assert_eq!(parse("-a * 2"), "((Neg a) Mul 2)");
+        assert_eq!(
+            parse("a::#number~>#string * 2"),
+            "((a :: number ~> string) Mul 2)"
+        );
+    }
+
+    #[test]
+    fn call_argument_is_the_rest_of_the_expression() {
+        assert_eq!(parse("f a + 1"), "(f (a Add 1))");
+        assert_eq!(parse("(f a) + 1"), "((f a) Add 1)");
+    }
+
+    #[test]
+    fn decimals_keep_their_digits() {
+        assert_eq!(parse("12345678901234567890.5"), "12345678901234567890.5");
+        assert_eq!(parse("1_000.25"), "1000.25");
+    }
+
+    #[test]
+    fn format_strings_split_into_text_and_interpolations() {
+        let expr = Lexer::new(r#"f"{a}.{b}{{""#, None)
+            .parse_expression()
+            .unwrap();
+        let parts = match expr.data {
+            Expr::Format(parts) => parts,
+            e => panic!("not a format string: {:?}", e),
+        };
+        let parts = parts
+            .iter()
+            .map(|p| match p {
+                FormatPart::Text(t) => format!("{:?}", t),
+                FormatPart::Interpolation(e) => shape(e),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(parts, ["a", "\".\"", "b", "\"{\""]);
+    }
+
+    #[test]
+    fn errors_show_tokens_as_written() {
+        assert_eq!(
+            syntax_error("main := a -> a );"),
+            "Expected `;` or `|`, found `)`"
+        );
+        assert_eq!(
+            syntax_error("main := a::#foo~>#string -> a;"),
+            "`#foo` is not a valid type"
+        );
+    }
+}